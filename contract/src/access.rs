@@ -0,0 +1,70 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::pagination::paginate;
+use crate::index::{set_insert, set_len, set_remove};
+use crate::errors::{require, PaydiiError};
+use crate::StorageKey;
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use near_sdk::json_types::{U128, U64};
+
+// roles the owner can grant, the owner itself passes every role check
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+  Admin, // manages categories and the allowed NFT contracts
+  Moderator, // moderates reviews
+  Pauser, // pauses parts of the marketplace
+  Arbitrator, // settles disputes between buyers and sellers
+}
+
+#[near_bindgen]
+impl Contract {
+
+  pub fn get_owner(&self) -> AccountId {
+    self.owner_id.clone()
+  }
+
+  // the new owner takes over at once, so double check the account id
+  pub fn transfer_ownership(&mut self, new_owner_id: AccountId) -> AccountId {
+    self.assert_owner();
+    self.owner_id = new_owner_id;
+    self.owner_id.clone()
+  }
+
+  pub fn grant_role(&mut self, role: Role, account_id: AccountId) -> bool {
+    self.assert_owner();
+    set_insert(&mut self.role_members, StorageKey::RoleMembersInner, &role, &account_id)
+  }
+
+  pub fn revoke_role(&mut self, role: Role, account_id: AccountId) -> bool {
+    self.assert_owner();
+    set_remove(&mut self.role_members, &role, &account_id)
+  }
+
+  pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+    self.role_members.get(&role).is_some_and(|members| members.contains(&account_id))
+  }
+
+  pub fn get_role_members(&self, role: Role, from_index: Option<U128>, limit: Option<u64>) -> Vec<AccountId> {
+    self.role_members.get(&role).map_or(vec![], |members| paginate(members.iter(), from_index, limit).collect())
+  }
+
+  pub fn get_role_members_count(&self, role: Role) -> U64 {
+    U64(set_len(&self.role_members, &role))
+  }
+}
+
+impl Contract {
+  pub(crate) fn assert_owner(&self) {
+    require(env::predecessor_account_id() == self.owner_id, PaydiiError::NotContractOwner);
+  }
+
+  // guard for administrative methods, the owner can do everything a role can
+  pub(crate) fn assert_role(&self, role: Role) {
+    let caller = env::predecessor_account_id();
+    require(caller == self.owner_id || self.has_role(role, caller), PaydiiError::MissingRole(role));
+  }
+}
@@ -0,0 +1,131 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::paydii::Product;
+use crate::rating::ProductView;
+use crate::access::Role;
+use crate::errors::{require, PaydiiError};
+use crate::events::{emit_event, ProductEvent};
+use crate::pagination::paginate;
+use crate::index::{set_insert, set_len, set_remove};
+use crate::StorageKey;
+
+use near_sdk::{env, near_bindgen};
+use near_sdk::collections::{UnorderedMap, UnorderedSet};
+use near_sdk::json_types::U128;
+
+pub const MAX_TAGS_PER_PRODUCT: usize = 10;
+pub const MAX_TAG_LENGTH: usize = 32;
+
+#[near_bindgen]
+impl Contract {
+
+  // only the owner or an admin can curate categories
+  pub fn add_category(&mut self, category: String) -> bool {
+    self.assert_role(Role::Admin);
+    let category = normalize(&category);
+    require(!category.is_empty(), PaydiiError::InvalidCategory);
+    self.categories.insert(&category)
+  }
+
+  // only the owner or an admin, and only empty categories can be removed
+  pub fn remove_category(&mut self, category: String) -> bool {
+    self.assert_role(Role::Admin);
+    let category = normalize(&category);
+    require(set_len(&self.products_by_category, &category) == 0, PaydiiError::CategoryNotEmpty(category.clone()));
+    self.categories.remove(&category)
+  }
+
+  pub fn get_categories(&self) -> Vec<String> {
+    self.categories.to_vec()
+  }
+
+  #[payable] // index entries are charged to the seller like the product itself
+  pub fn set_product_category(&mut self, product_id: String, category: Option<String>) -> Product {
+    let initial_storage = env::storage_usage();
+    let mut product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(!product.is_archived, PaydiiError::ProductArchived);
+
+    let category = category.map(|category| normalize(&category));
+    if let Some(category) = &category {
+      require(self.categories.contains(category), PaydiiError::CategoryNotFound(category.clone()));
+    }
+
+    self.remove_from_catalog_indexes(&product);
+    product.category = category;
+    self.add_to_catalog_indexes(&product);
+    self.products.insert(&product_id, &product.clone().into());
+    self.settle_storage(&product.seller, &product_id, initial_storage);
+    emit_event("product_updated", ProductEvent::from(&product));
+    product
+  }
+
+  // replaces the product's tags, tags are lowercased and duplicates are dropped
+  #[payable]
+  pub fn set_product_tags(&mut self, product_id: String, tags: Vec<String>) -> Product {
+    let initial_storage = env::storage_usage();
+    let mut product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(!product.is_archived, PaydiiError::ProductArchived);
+
+    let mut normalized_tags: Vec<String> = vec![];
+    for tag in tags.iter().map(|tag| normalize(tag)) {
+      require(!tag.is_empty() && tag.len() <= MAX_TAG_LENGTH, PaydiiError::InvalidTag { max_length: MAX_TAG_LENGTH });
+      if !normalized_tags.contains(&tag) {
+        normalized_tags.push(tag);
+      }
+    }
+    require(normalized_tags.len() <= MAX_TAGS_PER_PRODUCT, PaydiiError::TooManyTags { max: MAX_TAGS_PER_PRODUCT });
+
+    self.remove_from_catalog_indexes(&product);
+    product.tags = normalized_tags;
+    self.add_to_catalog_indexes(&product);
+    self.products.insert(&product_id, &product.clone().into());
+    self.settle_storage(&product.seller, &product_id, initial_storage);
+    emit_event("product_updated", ProductEvent::from(&product));
+    product
+  }
+
+  pub fn get_products_by_tag(&self, tag: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<ProductView> {
+    self.paginate_products(&self.products_by_tag, &normalize(&tag), from_index, limit)
+  }
+
+  pub fn get_products_by_category(&self, category: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<ProductView> {
+    self.paginate_products(&self.products_by_category, &normalize(&category), from_index, limit)
+  }
+}
+
+fn normalize(name: &str) -> String {
+  name.trim().to_lowercase()
+}
+
+impl Contract {
+  fn paginate_products(&self, index: &UnorderedMap<String, UnorderedSet<String>>, key: &String, from_index: Option<U128>, limit: Option<u64>) -> Vec<ProductView> {
+    let product_ids = match index.get(key) {
+      Some(product_ids) => product_ids,
+      None => return vec![],
+    };
+
+    paginate(product_ids.iter(), from_index, limit)
+      .filter_map(|product_id| self.products.get(&product_id).map(|product| self.product_view(product.into())))
+      .collect()
+  }
+
+  pub(crate) fn add_to_catalog_indexes(&mut self, product: &Product) {
+    if let Some(category) = &product.category {
+      set_insert(&mut self.products_by_category, StorageKey::ProductsByCategoryInner, category, &product.id);
+    }
+    for tag in product.tags.iter() {
+      set_insert(&mut self.products_by_tag, StorageKey::ProductsByTagInner, tag, &product.id);
+    }
+  }
+
+  pub(crate) fn remove_from_catalog_indexes(&mut self, product: &Product) {
+    if let Some(category) = &product.category {
+      set_remove(&mut self.products_by_category, category, &product.id);
+    }
+    for tag in product.tags.iter() {
+      set_remove(&mut self.products_by_tag, tag, &product.id);
+    }
+  }
+}
@@ -0,0 +1,259 @@
+use crate::access::Role;
+use crate::pause::PausableFeature;
+use crate::rating::{MIN_STAR, MAX_STAR};
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::{env, AccountId};
+use std::fmt;
+
+// Every failure the contract reports. Panics carry `{"code": "...", "message": "..."}` so
+// frontends can match on `code`, which never changes, and show their own text for it.
+// `message` is an English description for logs and explorers.
+#[derive(Debug)]
+pub enum PaydiiError {
+  // products
+  ProductNotFound(String),
+  ProductExists(String),
+  NotOwner,
+  ProductArchived,
+  ProductInactive,
+  ProductHasBuyers(String),
+  ProductHasCoupons { max: usize },
+  PriceBelowMinPrice { price: u128, min_price: u128 },
+  MinPriceAbovePrice { min_price: u128, price: u128 },
+  AlreadyOwner,
+  TransferNotProposed(String),
+  TooManyCouponsToTransfer { max: usize },
+  // purchases
+  OwnProduct,
+  AlreadyPurchased,
+  InsufficientDeposit { required: u128, attached: u128 },
+  // coupons
+  CouponNotFound,
+  CouponExists,
+  CouponInactive,
+  CouponExhausted,
+  DiscountTooHigh { discount: u128, max_discount: u128, product_id: String },
+  CouponBelowMinPrice { discount: u128, product_id: String, min_price: u128 },
+  EmptyCouponBatch,
+  CouponBatchTooLarge { max: usize },
+  EmptyCouponCode,
+  CouponCodeTooLong { max_length: usize },
+  InvalidCouponHash,
+  NftContractNotAllowed(AccountId),
+  NftNotHeld,
+  // reviews
+  AlreadyReviewed,
+  ReviewNotFound(AccountId),
+  OwnReview,
+  AlreadyVoted,
+  AlreadyReported,
+  InvalidReportReason { max_length: usize },
+  ReviewHidden,
+  ReviewNotHidden,
+  InvalidStar,
+  TooManyReviewMedia { max: usize },
+  InvalidReviewMedia { max_length: usize },
+  InvalidMediaHash { max_length: usize },
+  // catalog
+  InvalidCategory,
+  CategoryNotFound(String),
+  CategoryNotEmpty(String),
+  InvalidTag { max_length: usize },
+  TooManyTags { max: usize },
+  // promotions
+  PromotionDiscountTooHigh { discount: u128, price: u128 },
+  InvalidPromotionPeriod,
+  InvalidPromotionPercent,
+  // refunds and disputes
+  ReceiptNotFound(u64),
+  NotReceiptSeller,
+  NotReceiptBuyer,
+  AlreadyRefunded,
+  DisputeExists,
+  DisputeNotFound,
+  DisputeResolved,
+  InvalidDisputeReason { max_length: usize },
+  // administration
+  NotContractOwner,
+  MissingRole(Role),
+  Paused(PausableFeature),
+  InsufficientStorageDeposit { required: u128 },
+}
+
+// an error as data, for results that report failures without panicking
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ErrorJson {
+  pub code: String,
+  pub message: String,
+}
+
+impl From<PaydiiError> for ErrorJson {
+  fn from(error: PaydiiError) -> Self {
+    ErrorJson { code: error.code().to_string(), message: error.to_string() }
+  }
+}
+
+impl PaydiiError {
+  pub fn code(&self) -> &'static str {
+    match self {
+      PaydiiError::ProductNotFound(_) => "ProductNotFound",
+      PaydiiError::ProductExists(_) => "ProductExists",
+      PaydiiError::NotOwner => "NotOwner",
+      PaydiiError::ProductArchived => "ProductArchived",
+      PaydiiError::ProductInactive => "ProductInactive",
+      PaydiiError::ProductHasBuyers(_) => "ProductHasBuyers",
+      PaydiiError::ProductHasCoupons { .. } => "ProductHasCoupons",
+      PaydiiError::PriceBelowMinPrice { .. } => "PriceBelowMinPrice",
+      PaydiiError::MinPriceAbovePrice { .. } => "MinPriceAbovePrice",
+      PaydiiError::AlreadyOwner => "AlreadyOwner",
+      PaydiiError::TransferNotProposed(_) => "TransferNotProposed",
+      PaydiiError::TooManyCouponsToTransfer { .. } => "TooManyCouponsToTransfer",
+      PaydiiError::OwnProduct => "OwnProduct",
+      PaydiiError::AlreadyPurchased => "AlreadyPurchased",
+      PaydiiError::InsufficientDeposit { .. } => "InsufficientDeposit",
+      PaydiiError::CouponNotFound => "CouponNotFound",
+      PaydiiError::CouponExists => "CouponExists",
+      PaydiiError::CouponInactive => "CouponInactive",
+      PaydiiError::CouponExhausted => "CouponExhausted",
+      PaydiiError::DiscountTooHigh { .. } => "DiscountTooHigh",
+      PaydiiError::CouponBelowMinPrice { .. } => "CouponBelowMinPrice",
+      PaydiiError::EmptyCouponBatch => "EmptyCouponBatch",
+      PaydiiError::CouponBatchTooLarge { .. } => "CouponBatchTooLarge",
+      PaydiiError::EmptyCouponCode => "EmptyCouponCode",
+      PaydiiError::CouponCodeTooLong { .. } => "CouponCodeTooLong",
+      PaydiiError::InvalidCouponHash => "InvalidCouponHash",
+      PaydiiError::NftContractNotAllowed(_) => "NftContractNotAllowed",
+      PaydiiError::NftNotHeld => "NftNotHeld",
+      PaydiiError::AlreadyReviewed => "AlreadyReviewed",
+      PaydiiError::ReviewNotFound(_) => "ReviewNotFound",
+      PaydiiError::OwnReview => "OwnReview",
+      PaydiiError::AlreadyVoted => "AlreadyVoted",
+      PaydiiError::AlreadyReported => "AlreadyReported",
+      PaydiiError::InvalidReportReason { .. } => "InvalidReportReason",
+      PaydiiError::ReviewHidden => "ReviewHidden",
+      PaydiiError::ReviewNotHidden => "ReviewNotHidden",
+      PaydiiError::InvalidStar => "InvalidStar",
+      PaydiiError::TooManyReviewMedia { .. } => "TooManyReviewMedia",
+      PaydiiError::InvalidReviewMedia { .. } => "InvalidReviewMedia",
+      PaydiiError::InvalidMediaHash { .. } => "InvalidMediaHash",
+      PaydiiError::InvalidCategory => "InvalidCategory",
+      PaydiiError::CategoryNotFound(_) => "CategoryNotFound",
+      PaydiiError::CategoryNotEmpty(_) => "CategoryNotEmpty",
+      PaydiiError::InvalidTag { .. } => "InvalidTag",
+      PaydiiError::TooManyTags { .. } => "TooManyTags",
+      PaydiiError::PromotionDiscountTooHigh { .. } => "PromotionDiscountTooHigh",
+      PaydiiError::InvalidPromotionPeriod => "InvalidPromotionPeriod",
+      PaydiiError::InvalidPromotionPercent => "InvalidPromotionPercent",
+      PaydiiError::ReceiptNotFound(_) => "ReceiptNotFound",
+      PaydiiError::NotReceiptSeller => "NotReceiptSeller",
+      PaydiiError::NotReceiptBuyer => "NotReceiptBuyer",
+      PaydiiError::AlreadyRefunded => "AlreadyRefunded",
+      PaydiiError::DisputeExists => "DisputeExists",
+      PaydiiError::DisputeNotFound => "DisputeNotFound",
+      PaydiiError::DisputeResolved => "DisputeResolved",
+      PaydiiError::InvalidDisputeReason { .. } => "InvalidDisputeReason",
+      PaydiiError::NotContractOwner => "NotContractOwner",
+      PaydiiError::MissingRole(_) => "MissingRole",
+      PaydiiError::Paused(_) => "Paused",
+      PaydiiError::InsufficientStorageDeposit { .. } => "InsufficientStorageDeposit",
+    }
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(&ErrorJson { code: self.code().to_string(), message: self.to_string() }).unwrap()
+  }
+
+  // like near_sdk::require!, debug builds use a plain panic so unit tests can catch it
+  pub fn panic(&self) -> ! {
+    if cfg!(debug_assertions) {
+      panic!("{}", self.to_json())
+    } else {
+      env::panic_str(&self.to_json())
+    }
+  }
+}
+
+impl fmt::Display for PaydiiError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PaydiiError::ProductNotFound(product_id) => write!(f, "Product {} does not exist", product_id),
+      PaydiiError::ProductExists(product_id) => write!(f, "Product {} already exists", product_id),
+      PaydiiError::NotOwner => write!(f, "You are not the product's owner"),
+      PaydiiError::ProductArchived => write!(f, "Product is archived"),
+      PaydiiError::ProductInactive => write!(f, "Product is inactive"),
+      PaydiiError::ProductHasBuyers(product_id) => write!(f, "Product {} has buyers, archive it instead", product_id),
+      PaydiiError::ProductHasCoupons { max } => write!(f, "Product has more than {} coupons, remove them with delete_product_coupons first", max),
+      PaydiiError::PriceBelowMinPrice { price, min_price } =>
+        write!(f, "Price {} is lower than the product's minimum price {}", price, min_price),
+      PaydiiError::MinPriceAbovePrice { min_price, price } =>
+        write!(f, "Minimum price {} is higher than the product's price {}", min_price, price),
+      PaydiiError::AlreadyOwner => write!(f, "You already own this product"),
+      PaydiiError::TransferNotProposed(product_id) => write!(f, "No transfer of product {} is proposed to you", product_id),
+      PaydiiError::TooManyCouponsToTransfer { max } => write!(f, "At most {} coupons can move with a product, delete some coupons first", max),
+      PaydiiError::OwnProduct => write!(f, "You can't buy or review your own product"),
+      PaydiiError::AlreadyPurchased => write!(f, "You already bought this product"),
+      PaydiiError::InsufficientDeposit { required, attached } =>
+        write!(f, "Attached deposit {} is lower than the price {}", attached, required),
+      PaydiiError::CouponNotFound => write!(f, "This coupon does not exist"),
+      PaydiiError::CouponExists => write!(f, "This coupon already exists for this product"),
+      PaydiiError::CouponInactive => write!(f, "This coupon is inactive"),
+      PaydiiError::CouponExhausted => write!(f, "This coupon has no uses left"),
+      PaydiiError::DiscountTooHigh { discount, max_discount, product_id } =>
+        write!(f, "Coupon discount {} is bigger than the maximum discount {} for product {}", discount, max_discount, product_id),
+      PaydiiError::CouponBelowMinPrice { discount, product_id, min_price } =>
+        write!(f, "A coupon gives a discount of {}, which would bring product {} under its minimum price {}. Update or delete the coupon first",
+          discount, product_id, min_price),
+      PaydiiError::EmptyCouponBatch => write!(f, "No coupon codes given"),
+      PaydiiError::CouponBatchTooLarge { max } => write!(f, "At most {} coupons can be created in one call", max),
+      PaydiiError::EmptyCouponCode => write!(f, "Coupon code is empty"),
+      PaydiiError::CouponCodeTooLong { max_length } => write!(f, "Coupon code is longer than {} characters", max_length),
+      PaydiiError::InvalidCouponHash => write!(f, "Coupon code hash must be 64 lowercase hex characters"),
+      PaydiiError::NftContractNotAllowed(nft_contract_id) => write!(f, "NFT contract {} is not allowed for coupons", nft_contract_id),
+      PaydiiError::NftNotHeld => write!(f, "You don't hold a token from this coupon's NFT contract"),
+      PaydiiError::AlreadyReviewed => write!(f, "You already reviewed this product"),
+      PaydiiError::ReviewNotFound(reviewer) => write!(f, "{} hasn't reviewed this product", reviewer),
+      PaydiiError::OwnReview => write!(f, "You can't vote on your own review"),
+      PaydiiError::AlreadyVoted => write!(f, "You already voted on this review"),
+      PaydiiError::AlreadyReported => write!(f, "You already reported this review"),
+      PaydiiError::InvalidReportReason { max_length } => write!(f, "Report reason must be 1 to {} characters long", max_length),
+      PaydiiError::ReviewHidden => write!(f, "This review is hidden by a moderator"),
+      PaydiiError::ReviewNotHidden => write!(f, "This review is not hidden"),
+      PaydiiError::InvalidStar => write!(f, "Star must be between {} and {}", MIN_STAR, MAX_STAR),
+      PaydiiError::TooManyReviewMedia { max } => write!(f, "A review can have at most {} attachments", max),
+      PaydiiError::InvalidReviewMedia { max_length } =>
+        write!(f, "Attachments must be an IPFS CID, an ipfs:// link or an https:// URL of at most {} characters", max_length),
+      PaydiiError::InvalidMediaHash { max_length } => write!(f, "Attachment hashes must be 1 to {} characters long", max_length),
+      PaydiiError::InvalidCategory => write!(f, "Category name is empty"),
+      PaydiiError::CategoryNotFound(category) => write!(f, "Category {} does not exist", category),
+      PaydiiError::CategoryNotEmpty(category) => write!(f, "Category {} still has products", category),
+      PaydiiError::InvalidTag { max_length } => write!(f, "Tags must be 1 to {} characters long", max_length),
+      PaydiiError::TooManyTags { max } => write!(f, "A product can have at most {} tags", max),
+      PaydiiError::PromotionDiscountTooHigh { discount, price } =>
+        write!(f, "Promotion discount {} is bigger than the product's price {}", discount, price),
+      PaydiiError::InvalidPromotionPeriod => write!(f, "Promotion must end after it starts"),
+      PaydiiError::InvalidPromotionPercent => write!(f, "Promotion percent must be between 1 and 100"),
+      PaydiiError::ReceiptNotFound(id) => write!(f, "Receipt {} does not exist", id),
+      PaydiiError::NotReceiptSeller => write!(f, "Only the seller who was paid can refund the purchase"),
+      PaydiiError::NotReceiptBuyer => write!(f, "Only the buyer can dispute the purchase"),
+      PaydiiError::AlreadyRefunded => write!(f, "Purchase is already refunded"),
+      PaydiiError::DisputeExists => write!(f, "Purchase is already disputed"),
+      PaydiiError::DisputeNotFound => write!(f, "Purchase is not disputed"),
+      PaydiiError::DisputeResolved => write!(f, "Dispute is already resolved"),
+      PaydiiError::InvalidDisputeReason { max_length } => write!(f, "Dispute reason must be 1 to {} characters long", max_length),
+      PaydiiError::NotContractOwner => write!(f, "Only the owner can call this method"),
+      PaydiiError::MissingRole(role) => write!(f, "Only the owner or an account with the {:?} role can call this method", role),
+      PaydiiError::Paused(feature) => write!(f, "This action is paused ({:?})", feature),
+      PaydiiError::InsufficientStorageDeposit { required } => write!(f, "Attach at least {} yoctoNEAR to cover storage", required),
+    }
+  }
+}
+
+// `assert!` for contract methods, panics with the error's JSON
+pub(crate) fn require(condition: bool, error: PaydiiError) {
+  if !condition {
+    error.panic()
+  }
+}
@@ -0,0 +1,160 @@
+use crate::paydii::Product;
+use crate::pause::PausableFeature;
+use crate::promotion::PromotionScope;
+
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{log, AccountId};
+
+// NEP-297 events, indexers pick up every log line starting with `EVENT_JSON:`.
+// Bump the version whenever the data of an event changes
+pub const EVENT_STANDARD: &str = "paydii";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T: Serialize> {
+  standard: &'a str,
+  version: &'a str,
+  event: &'a str,
+  data: Vec<T>,
+}
+
+pub(crate) fn emit_event<T: Serialize>(event: &str, data: T) {
+  emit_events(event, vec![data]);
+}
+
+// one log line for many items
+pub(crate) fn emit_events<T: Serialize>(event: &str, data: Vec<T>) {
+  let event_log = EventLog { standard: EVENT_STANDARD, version: EVENT_VERSION, event, data };
+  log!("EVENT_JSON:{}", serde_json::to_string(&event_log).unwrap());
+}
+
+// the whole product, so product_created and product_updated are enough to rebuild it
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductEvent {
+  pub id: String,
+  pub name: String,
+  pub price: U128,
+  pub min_price: U128,
+  pub description: String,
+  pub img: String,
+  pub is_active: bool,
+  pub seller: AccountId,
+  pub is_archived: bool,
+  pub category: Option<String>,
+  pub tags: Vec<String>,
+}
+
+impl From<&Product> for ProductEvent {
+  fn from(product: &Product) -> Self {
+    ProductEvent {
+      id: product.id.clone(),
+      name: product.name.clone(),
+      price: U128(product.price),
+      min_price: U128(product.min_price),
+      description: product.description.clone(),
+      img: product.img.clone(),
+      is_active: product.is_active,
+      seller: product.seller.clone(),
+      is_archived: product.is_archived,
+      category: product.category.clone(),
+      tags: product.tags.clone(),
+    }
+  }
+}
+
+// product_deleted, the product and its coupons, promotion and reviews are gone
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductDeletedEvent {
+  pub id: String,
+  pub seller: AccountId,
+}
+
+// coupons_created, one line for a batch of coupons sharing the same settings
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponsCreatedEvent {
+  pub product_id: String,
+  pub seller: AccountId,
+  pub codes: Vec<String>,
+  pub discount_amount: U128,
+  pub allowed_uses: U128,
+  pub nft_contract_id: Option<AccountId>,
+  pub is_hashed: bool,
+}
+
+// coupons_deleted, coupons of one product removed in one call
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponsDeletedEvent {
+  pub product_id: String,
+  pub seller: AccountId,
+  pub codes: Vec<String>,
+}
+
+// coupons_transferred, every coupon of the product is now keyed by `new_seller`. One line for all of
+// them keeps the transfer under the log limit
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponsTransferredEvent {
+  pub product_id: String,
+  pub old_seller: AccountId,
+  pub new_seller: AccountId,
+  pub count: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseEvent {
+  pub receipt_id: U64,
+  pub buyer: AccountId,
+  pub seller: AccountId,
+  pub product_id: String,
+  pub price: U128, // the product's price before discounts
+  pub paid_price: U128,
+  pub coupon_code: Option<String>,
+  pub coupon_discount: U128,
+  pub promotion: Option<PromotionScope>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseRefundedEvent {
+  pub receipt_id: U64,
+  pub buyer: AccountId,
+  pub seller: AccountId,
+  pub product_id: String,
+  pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponRedeemedEvent {
+  pub product_id: String,
+  pub code: String,
+  pub seller: AccountId,
+  pub buyer: AccountId,
+  pub discount_amount: U128,
+  pub paid_price: U128,
+  pub remaining_uses: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReviewVoteEvent {
+  pub product_id: String,
+  pub reviewer: AccountId,
+  pub voter: AccountId,
+  pub helpful: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseEvent {
+  pub feature: PausableFeature,
+  pub account_id: AccountId,
+}
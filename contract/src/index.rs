@@ -0,0 +1,104 @@
+use crate::StorageKey;
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+use near_sdk::collections::{TreeMap, UnorderedMap, UnorderedSet, Vector};
+
+// Helpers for the per-key collections nested in the contract's maps. Every nested collection
+// gets its own storage prefix from the hash of its outer key, so adding one item only writes
+// that item and the small collection header, no matter how many items the key already has.
+// The header is stored as the outer map's value, so it is written back after every change.
+
+pub(crate) fn nested_prefix<K: BorshSerialize>(inner: fn(Vec<u8>) -> StorageKey, key: &K) -> StorageKey {
+  inner(env::sha256(&key.try_to_vec().unwrap()))
+}
+
+pub(crate) fn set_insert<K, V>(map: &mut UnorderedMap<K, UnorderedSet<V>>, inner: fn(Vec<u8>) -> StorageKey, key: &K, value: &V) -> bool
+where K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut set = map.get(key).unwrap_or_else(|| UnorderedSet::new(nested_prefix(inner, key)));
+  let inserted = set.insert(value);
+  map.insert(key, &set);
+  inserted
+}
+
+// removes the value, and the whole entry once the set is empty
+pub(crate) fn set_remove<K, V>(map: &mut UnorderedMap<K, UnorderedSet<V>>, key: &K, value: &V) -> bool
+where K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut set = match map.get(key) {
+    Some(set) => set,
+    None => return false,
+  };
+  let removed = set.remove(value);
+  if set.is_empty() {
+    map.remove(key);
+  } else {
+    map.insert(key, &set);
+  }
+  removed
+}
+
+pub(crate) fn set_len<K, V>(map: &UnorderedMap<K, UnorderedSet<V>>, key: &K) -> u64
+where K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  map.get(key).map_or(0, |set| set.len())
+}
+
+pub(crate) fn vector_push<K, V>(map: &mut UnorderedMap<K, Vector<V>>, inner: fn(Vec<u8>) -> StorageKey, key: &K, value: &V)
+where K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut vector = map.get(key).unwrap_or_else(|| Vector::new(nested_prefix(inner, key)));
+  vector.push(value);
+  map.insert(key, &vector);
+}
+
+pub(crate) fn vector_len<K, V>(map: &UnorderedMap<K, Vector<V>>, key: &K) -> u64
+where K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  map.get(key).map_or(0, |vector| vector.len())
+}
+
+pub(crate) fn map_insert<K, IK, V>(map: &mut UnorderedMap<K, UnorderedMap<IK, V>>, inner: fn(Vec<u8>) -> StorageKey, key: &K, inner_key: &IK, value: &V) -> Option<V>
+where K: BorshSerialize + BorshDeserialize, IK: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut inner_map = map.get(key).unwrap_or_else(|| UnorderedMap::new(nested_prefix(inner, key)));
+  let previous = inner_map.insert(inner_key, value);
+  map.insert(key, &inner_map);
+  previous
+}
+
+// removes the inner entry, and the whole entry once the inner map is empty
+pub(crate) fn map_remove<K, IK, V>(map: &mut UnorderedMap<K, UnorderedMap<IK, V>>, key: &K, inner_key: &IK) -> Option<V>
+where K: BorshSerialize + BorshDeserialize, IK: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut inner_map = map.get(key)?;
+  let removed = inner_map.remove(inner_key);
+  if inner_map.is_empty() {
+    map.remove(key);
+  } else {
+    map.insert(key, &inner_map);
+  }
+  removed
+}
+
+pub(crate) fn tree_insert<K, IK, V>(map: &mut UnorderedMap<K, TreeMap<IK, V>>, inner: fn(Vec<u8>) -> StorageKey, key: &K, inner_key: &IK, value: &V) -> Option<V>
+where K: BorshSerialize + BorshDeserialize, IK: Ord + Clone + BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut tree = map.get(key).unwrap_or_else(|| TreeMap::new(nested_prefix(inner, key)));
+  let previous = tree.insert(inner_key, value);
+  map.insert(key, &tree);
+  previous
+}
+
+// removes the inner key, and the whole entry once the tree is empty
+pub(crate) fn tree_remove<K, IK, V>(map: &mut UnorderedMap<K, TreeMap<IK, V>>, key: &K, inner_key: &IK) -> Option<V>
+where K: BorshSerialize + BorshDeserialize, IK: Ord + Clone + BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  let mut tree = map.get(key)?;
+  let removed = tree.remove(inner_key);
+  if tree.is_empty() {
+    map.remove(key);
+  } else {
+    map.insert(key, &tree);
+  }
+  removed
+}
+
+pub(crate) fn tree_clear<K, IK, V>(map: &mut UnorderedMap<K, TreeMap<IK, V>>, key: &K)
+where K: BorshSerialize + BorshDeserialize, IK: Ord + Clone + BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize {
+  if let Some(mut tree) = map.remove(key) {
+    tree.clear();
+  }
+}
@@ -0,0 +1,38 @@
+use crate::errors::{require, PaydiiError};
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+
+pub const MAX_REVIEW_MEDIA: usize = 4;
+pub const MAX_MEDIA_URI_LENGTH: usize = 256;
+pub const MAX_MEDIA_HASH_LENGTH: usize = 128;
+
+// a screenshot or other file attached to a review, the file itself lives off chain.
+// `uri` is an IPFS CID, an `ipfs://` link or an `https://` URL, `hash` lets clients check what they downloaded
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReviewMedia {
+  pub uri: String,
+  pub hash: Option<String>,
+}
+
+pub(crate) fn validate_review_media(media: &[ReviewMedia]) {
+  require(media.len() <= MAX_REVIEW_MEDIA, PaydiiError::TooManyReviewMedia { max: MAX_REVIEW_MEDIA });
+  for item in media {
+    require(is_valid_uri(&item.uri), PaydiiError::InvalidReviewMedia { max_length: MAX_MEDIA_URI_LENGTH });
+    if let Some(hash) = &item.hash {
+      require(!hash.is_empty() && hash.len() <= MAX_MEDIA_HASH_LENGTH, PaydiiError::InvalidMediaHash { max_length: MAX_MEDIA_HASH_LENGTH });
+    }
+  }
+}
+
+fn is_valid_uri(uri: &str) -> bool {
+  if uri.is_empty() || uri.len() > MAX_MEDIA_URI_LENGTH || uri.chars().any(|c| c.is_whitespace() || c.is_control()) {
+    return false;
+  }
+  match uri.strip_prefix("ipfs://").or_else(|| uri.strip_prefix("https://")) {
+    Some(rest) => !rest.is_empty(),
+    // a bare CID
+    None => uri.chars().all(|c| c.is_ascii_alphanumeric()),
+  }
+}
@@ -0,0 +1,131 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::paydii::{CouponKey, Product, Review, TrackingKey};
+use crate::index::{map_insert, set_insert, vector_push};
+use crate::versioned::{read_state_version, write_state_version, ProductV1, CouponV1, ReviewV1, PurchaseInfoV1, VersionedProduct, VersionedCoupon, VersionedReview, VersionedPurchaseInfo, STATE_VERSION};
+use crate::StorageKey;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use near_sdk::collections::UnorderedMap;
+
+// layout of the first release, where every index was a Vec stored as one map value
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct ContractV1 {
+  pub(crate) product_list: Vec<String>,
+  pub(crate) products: UnorderedMap<String, ProductV1>,
+  pub(crate) products_by_sellers: UnorderedMap<AccountId, Vec<String>>,
+  pub(crate) buyer_addresses: UnorderedMap<String, Vec<AccountId>>,
+  pub(crate) coupons: UnorderedMap<CouponKey, CouponV1>,
+  pub(crate) coupons_by_seller: UnorderedMap<AccountId, Vec<CouponKey>>,
+  pub(crate) reviews: UnorderedMap<String, Vec<ReviewV1>>,
+  pub(crate) my_reviews: UnorderedMap<AccountId, Vec<ReviewV1>>,
+  pub(crate) tracking: UnorderedMap<TrackingKey, bool>,
+  pub(crate) buyers: UnorderedMap<AccountId, Vec<PurchaseInfoV1>>,
+}
+
+#[near_bindgen]
+impl Contract {
+  // Public - but only callable by env::current_account_id(). Upgrades the state written by any
+  // earlier release to the current layout, and does nothing if the state is already current
+  #[private]
+  #[init(ignore_state)]
+  pub fn migrate() -> Self {
+    let contract = match read_state_version() {
+      1 => Self::migrate_from_v1(),
+      STATE_VERSION => env::state_read().expect("Contract is not initialized"),
+      version => env::panic_str(&format!("Unknown state version {}", version)),
+    };
+    write_state_version();
+    contract
+  }
+
+  pub fn get_state_version(&self) -> u8 {
+    read_state_version()
+  }
+}
+
+impl Contract {
+  // moves the first release's state to the per-key collections. Everything is moved in this call,
+  // so it is meant for the small testnet deployments made with the first release
+  fn migrate_from_v1() -> Self {
+    let mut old: ContractV1 = env::state_read().expect("Contract is not initialized");
+    let mut contract = Contract {
+      tracking: old.tracking,
+      ..Default::default()
+    };
+
+    // products and coupons keep their prefix, so they are read out before the map is rebuilt.
+    // They are stored as they are and upgraded to the current layout when they are read
+    let products = old.products.to_vec();
+    old.products.clear();
+    for (id, product) in products {
+      contract.products.insert(&id, &VersionedProduct::V1(product));
+    }
+
+    let coupons = old.coupons.to_vec();
+    old.coupons.clear();
+    for (key, coupon) in coupons {
+      set_insert(&mut contract.coupons_by_product, StorageKey::CouponsByProductInner, &key.product_id, &key);
+      contract.add_coupon_discount(&key.product_id, coupon.discount_amount, 1);
+      contract.coupons.insert(&key, &VersionedCoupon::V1(coupon));
+    }
+
+    for product_id in old.product_list.iter() {
+      contract.product_list.insert(product_id);
+    }
+
+    // sellers of the first release start their account age at the migration
+    for (seller, product_ids) in old.products_by_sellers.iter() {
+      contract.record_seller(&seller);
+      for product_id in product_ids.iter() {
+        set_insert(&mut contract.products_by_sellers, StorageKey::ProductBySellerInner, &seller, product_id);
+      }
+    }
+    old.products_by_sellers.clear();
+
+    for (product_id, buyers) in old.buyer_addresses.iter() {
+      for buyer in buyers.iter() {
+        set_insert(&mut contract.buyer_addresses, StorageKey::BuyerAddressesInner, &product_id, buyer);
+      }
+    }
+    old.buyer_addresses.clear();
+
+    for (seller, coupon_keys) in old.coupons_by_seller.iter() {
+      for key in coupon_keys.iter() {
+        set_insert(&mut contract.coupons_by_seller, StorageKey::CouponsBySellerInner, &seller, key);
+      }
+    }
+    old.coupons_by_seller.clear();
+
+    // `my_reviews` held copies of the same reviews, they are rebuilt from `reviews`.
+    // Reviews of buyers are marked as verified, `tracking` already holds the purchases
+    for (product_id, reviews) in old.reviews.iter() {
+      for review in reviews.into_iter() {
+        let review = Review {
+          verified: contract.has_purchased(&review.reviewer, &product_id),
+          ..Review::from(VersionedReview::V1(review))
+        };
+        set_insert(&mut contract.my_reviews, StorageKey::MyReviewsInner, &review.reviewer, &product_id);
+        // the first release took any star, those reviews stay readable but are left out of the rating
+        contract.add_to_rating(&product_id, review.star);
+        contract.reindex_review(&product_id, None, Some(&review));
+        map_insert(&mut contract.reviews, StorageKey::ReviewsInner, &product_id, &review.reviewer.clone(), &review.into());
+      }
+    }
+    old.reviews.clear();
+    old.my_reviews.clear();
+
+    for (buyer, purchases) in old.buyers.iter() {
+      for purchase in purchases.iter() {
+        vector_push(&mut contract.buyers, StorageKey::BuyersInner, &buyer, &VersionedPurchaseInfo::V1(purchase.clone()));
+        if let Some(product) = contract.products.get(&purchase.product_id).map(Product::from) {
+          contract.record_sale(&product.seller, purchase.profit_price);
+        }
+      }
+    }
+    old.buyers.clear();
+
+    contract
+  }
+}
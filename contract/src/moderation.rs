@@ -0,0 +1,147 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::paydii::Review;
+use crate::access::Role;
+use crate::errors::{require, PaydiiError};
+use crate::events::emit_event;
+use crate::pagination::paginate;
+use crate::index::map_insert;
+use crate::StorageKey;
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use near_sdk::json_types::{U128, U64};
+
+pub const MAX_REPORT_REASON_LENGTH: usize = 280;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReviewReport {
+  pub reporter: AccountId,
+  pub reason: String,
+  pub reported_at: U64,
+}
+
+// why a moderator hid a review, shown to the reviewer in `get_my_reviews`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReviewModeration {
+  pub moderator: AccountId,
+  pub reason: String,
+  pub hidden_at: U64,
+}
+
+// an entry of the moderation queue
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReportedReview {
+  pub review: Review,
+  pub reports: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ModerationEvent<'a> {
+  product_id: &'a str,
+  reviewer: &'a AccountId,
+  account_id: AccountId,
+  reason: &'a str,
+}
+
+#[near_bindgen]
+impl Contract {
+
+  // any account can report a review once, the review waits in the moderation queue
+  pub fn report_review(&mut self, product_id: String, reviewer: AccountId, reason: String) -> bool {
+    require(!reason.is_empty() && reason.len() <= MAX_REPORT_REASON_LENGTH, PaydiiError::InvalidReportReason { max_length: MAX_REPORT_REASON_LENGTH });
+    let review = self.expect_review(&product_id, &reviewer);
+    require(review.hidden.is_none(), PaydiiError::ReviewHidden);
+
+    let reporter = env::predecessor_account_id();
+    let report = ReviewReport { reporter: reporter.clone(), reason, reported_at: U64(env::block_timestamp()) };
+    let review_key = (product_id.clone(), reviewer.clone());
+    let previous_report = map_insert(&mut self.review_reports, StorageKey::ReviewReportsInner, &review_key, &reporter, &report);
+    require(previous_report.is_none(), PaydiiError::AlreadyReported);
+
+    emit_event("review_reported", ModerationEvent { product_id: &product_id, reviewer: &reviewer, account_id: reporter, reason: &report.reason });
+    true
+  }
+
+  // only the owner or a moderator. The review stays stored but leaves the public views and the rating
+  pub fn hide_review(&mut self, product_id: String, reviewer: AccountId, reason: String) -> Review {
+    self.assert_role(Role::Moderator);
+    let mut review = self.expect_review(&product_id, &reviewer);
+    require(review.hidden.is_none(), PaydiiError::ReviewHidden);
+
+    self.remove_from_rating(&product_id, review.star);
+    review.hidden = Some(ReviewModeration {
+      moderator: env::predecessor_account_id(),
+      reason: reason.clone(),
+      hidden_at: U64(env::block_timestamp()),
+    });
+    map_insert(&mut self.reviews, StorageKey::ReviewsInner, &product_id, &reviewer, &review.clone().into());
+    self.clear_review_reports(&product_id, &reviewer);
+    let hidden = self.hidden_review_counts.get(&product_id).unwrap_or(0);
+    self.hidden_review_counts.insert(&product_id, &(hidden + 1));
+
+    emit_event("review_hidden", ModerationEvent { product_id: &product_id, reviewer: &reviewer, account_id: env::predecessor_account_id(), reason: &reason });
+    review
+  }
+
+  // only the owner or a moderator
+  pub fn restore_review(&mut self, product_id: String, reviewer: AccountId) -> Review {
+    self.assert_role(Role::Moderator);
+    let mut review = self.expect_review(&product_id, &reviewer);
+    require(review.hidden.is_some(), PaydiiError::ReviewNotHidden);
+
+    review.hidden = None;
+    self.add_to_rating(&product_id, review.star);
+    match self.hidden_review_counts.get(&product_id).unwrap_or(0) {
+      0 | 1 => self.hidden_review_counts.remove(&product_id),
+      hidden => self.hidden_review_counts.insert(&product_id, &(hidden - 1)),
+    };
+    map_insert(&mut self.reviews, StorageKey::ReviewsInner, &product_id, &reviewer, &review.clone().into());
+
+    emit_event("review_restored", ModerationEvent { product_id: &product_id, reviewer: &reviewer, account_id: env::predecessor_account_id(), reason: "" });
+    review
+  }
+
+  // only the owner or a moderator. Takes a review out of the queue and keeps it visible
+  pub fn dismiss_review_reports(&mut self, product_id: String, reviewer: AccountId) -> bool {
+    self.assert_role(Role::Moderator);
+    self.clear_review_reports(&product_id, &reviewer)
+  }
+
+  // reported reviews waiting for a moderator
+  pub fn get_reported_reviews(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<ReportedReview> {
+    paginate(self.review_reports.keys(), from_index, limit)
+      .filter_map(|review_key| {
+        let reports = U64(self.review_reports.get(&review_key)?.len());
+        let (product_id, reviewer) = review_key;
+        let review = self.reviews.get(&product_id)?.get(&reviewer)?.into();
+        Some(ReportedReview { review, reports })
+      })
+      .collect()
+  }
+
+  pub fn get_reported_reviews_count(&self) -> U64 {
+    U64(self.review_reports.len())
+  }
+
+  pub fn get_review_reports(&self, product_id: String, reviewer: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<ReviewReport> {
+    self.review_reports.get(&(product_id, reviewer)).map_or(vec![], |reports| paginate(reports.values(), from_index, limit).collect())
+  }
+}
+
+impl Contract {
+  pub(crate) fn clear_review_reports(&mut self, product_id: &str, reviewer: &AccountId) -> bool {
+    match self.review_reports.remove(&(product_id.to_string(), reviewer.clone())) {
+      Some(mut reports) => {
+        reports.clear();
+        true
+      }
+      None => false,
+    }
+  }
+}
@@ -0,0 +1,78 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::access::Role;
+use crate::errors::PaydiiError;
+
+use near_sdk::{env, ext_contract, log, near_bindgen, AccountId, Gas, Promise, PromiseError};
+use near_sdk::json_types::U128;
+
+const GAS_FOR_NFT_SUPPLY: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_NFT_HOLDER_CALLBACK: Gas = Gas(40_000_000_000_000);
+
+// NEP-171 enumeration method used to check that a buyer holds a token
+#[allow(dead_code)]
+#[ext_contract(ext_nft)]
+pub trait NonFungibleTokenEnumeration {
+  fn nft_supply_for_owner(&self, account_id: AccountId) -> U128;
+}
+
+#[near_bindgen]
+impl Contract {
+
+  // only the owner or an admin. Allows an NFT contract for token-gated coupons
+  pub fn add_nft_contract(&mut self, nft_contract_id: AccountId) -> bool {
+    self.assert_role(Role::Admin);
+    self.nft_contract_ids.insert(&nft_contract_id)
+  }
+
+  // only the owner or an admin. Existing coupons of this contract stop working
+  pub fn remove_nft_contract(&mut self, nft_contract_id: AccountId) -> bool {
+    self.assert_role(Role::Admin);
+    self.nft_contract_ids.remove(&nft_contract_id)
+  }
+
+  pub fn get_nft_contracts(&self) -> Vec<AccountId> {
+    self.nft_contract_ids.to_vec()
+  }
+
+  // finishes a purchase with a token-gated coupon, the deposit goes back to the buyer if anything fails
+  #[private]
+  pub fn on_nft_holder_checked(&mut self, #[callback_result] supply: Result<U128, PromiseError>, buyer: AccountId, product_id: String, coupon_code: String, deposit: U128) -> bool {
+    let holds_token = matches!(supply, Ok(supply) if supply.0 > 0);
+
+    // the purchase is checked again, the state may have changed while waiting for the NFT contract
+    let result = if holds_token {
+      self.validate_purchase(&buyer, &product_id, Some(coupon_code))
+    } else {
+      Err(PaydiiError::NftNotHeld)
+    }.and_then(|(product, coupon)| {
+      let price = self.purchase_price(&product, coupon.as_ref());
+      if deposit.0 < price {
+        return Err(PaydiiError::InsufficientDeposit { required: price, attached: deposit.0 });
+      }
+      Ok((product, coupon))
+    });
+
+    match result {
+      Ok((product, coupon)) => self.complete_purchase(buyer, product, coupon, deposit.0),
+      Err(error) => {
+        log!("Purchase of {} by {} failed: {}", product_id, buyer, error.to_json());
+        Promise::new(buyer).transfer(deposit.0);
+        false
+      }
+    }
+  }
+}
+
+impl Contract {
+  pub(crate) fn check_nft_holder(&self, nft_contract_id: AccountId, buyer: AccountId, product_id: String, coupon_code: String, deposit: u128) -> Promise {
+    ext_nft::ext(nft_contract_id)
+      .with_static_gas(GAS_FOR_NFT_SUPPLY)
+      .nft_supply_for_owner(buyer.clone())
+      .then(
+        Self::ext(env::current_account_id())
+          .with_static_gas(GAS_FOR_NFT_HOLDER_CALLBACK)
+          .on_nft_holder_checked(buyer, product_id, coupon_code, U128(deposit))
+      )
+  }
+}
@@ -0,0 +1,11 @@
+use near_sdk::json_types::U128;
+
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+
+// skips `from_index` items and keeps at most `limit`, shared by every paginated view
+pub(crate) fn paginate<I: Iterator>(items: I, from_index: Option<U128>, limit: Option<u64>) -> std::iter::Take<std::iter::Skip<I>> {
+  // `as` would cut off the high bits, so anything past usize::MAX is clamped instead
+  let start = usize::try_from(from_index.map_or(0, |from_index| from_index.0)).unwrap_or(usize::MAX);
+  let limit = usize::try_from(limit.unwrap_or(DEFAULT_PAGE_SIZE)).unwrap_or(usize::MAX);
+  items.skip(start).take(limit)
+}
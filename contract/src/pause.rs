@@ -0,0 +1,57 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::access::Role;
+use crate::errors::{require, PaydiiError};
+use crate::events::{emit_event, PauseEvent};
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen};
+
+// parts of the marketplace that can be stopped on their own, views and refunds always keep working
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PausableFeature {
+  Purchases,
+  ProductCreation,
+  CouponRedemption,
+  Reviews,
+}
+
+#[near_bindgen]
+impl Contract {
+
+  // only the owner or a pauser
+  pub fn pause(&mut self, feature: PausableFeature) -> bool {
+    self.assert_role(Role::Pauser);
+    let paused = self.paused_features.insert(&feature);
+    if paused {
+      emit_event("paused", PauseEvent { feature, account_id: env::predecessor_account_id() });
+    }
+    paused
+  }
+
+  // only the owner or a pauser
+  pub fn unpause(&mut self, feature: PausableFeature) -> bool {
+    self.assert_role(Role::Pauser);
+    let unpaused = self.paused_features.remove(&feature);
+    if unpaused {
+      emit_event("unpaused", PauseEvent { feature, account_id: env::predecessor_account_id() });
+    }
+    unpaused
+  }
+
+  pub fn is_paused(&self, feature: PausableFeature) -> bool {
+    self.paused_features.contains(&feature)
+  }
+
+  pub fn get_paused_features(&self) -> Vec<PausableFeature> {
+    self.paused_features.to_vec()
+  }
+}
+
+impl Contract {
+  pub(crate) fn assert_not_paused(&self, feature: PausableFeature) {
+    require(!self.is_paused(feature), PaydiiError::Paused(feature));
+  }
+}
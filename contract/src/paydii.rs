@@ -1,16 +1,34 @@
 use crate::Contract;
 use crate::ContractExt;
+use crate::promotion::PromotionScope;
+use crate::pause::PausableFeature;
+use crate::moderation::ReviewModeration;
+use crate::media::{validate_review_media, ReviewMedia};
+use crate::receipts::{Receipt, ReceiptCoupon};
+use crate::rating::{ProductRatingJson, ProductView, MIN_STAR, MAX_STAR};
+use crate::errors::{require, ErrorJson, PaydiiError};
+use crate::pagination::paginate;
+use crate::index::{map_insert, map_remove, nested_prefix, set_insert, set_len, set_remove, tree_clear, tree_insert, tree_remove, vector_len, vector_push};
+use crate::events::{emit_event, ProductEvent, ProductDeletedEvent, CouponsCreatedEvent, CouponsDeletedEvent, CouponsTransferredEvent, PurchaseEvent, CouponRedeemedEvent, ReviewVoteEvent};
+use crate::StorageKey;
 
 
 use near_sdk::serde::Deserialize;
 // use near_sdk::env::log;
 use near_sdk::serde::Serialize;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{env, log, near_bindgen, AccountId, Promise, Balance};
+use near_sdk::{env, near_bindgen, AccountId, Promise, PromiseOrValue};
 use near_sdk::json_types::U128;
 use near_sdk::json_types::U64;
+use near_sdk::collections::UnorderedSet;
+use std::collections::BTreeMap;
 
 // pub const STORAGE_COST: u128 = 1_000_000_000_000_000_000_000;
+// a batch is written and logged in one call, this keeps it under the gas and log limits
+pub const MAX_COUPON_BATCH: usize = 50;
+pub const MAX_COUPON_CODE_LENGTH: usize = 64; // a sha256 hex hash fits
+// every coupon is stored again under the new seller, this keeps a transfer well within the gas limit
+pub const MAX_TRANSFER_COUPONS: usize = 50;
 
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
@@ -19,40 +37,76 @@ pub struct Product {
   pub id: String,
   pub name: String,
   pub price: u128,
-  pub description: String, 
-  pub img: String, 
+  pub description: String,
+  pub img: String,
   pub is_active: bool,
   pub seller: AccountId,
+  pub min_price: u128, // lowest price a coupon can bring the product down to
+  pub is_archived: bool, // archived products are kept for their buyers but hidden from listings
+  pub category: Option<String>, // one of the categories curated by the contract owner
+  pub tags: Vec<String>, // free-form tags set by the seller
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+impl Product {
+  // the biggest discount a coupon can give without going under `min_price`
+  fn max_discount(&self) -> u128 {
+    self.price.saturating_sub(self.min_price)
+  }
+
+  // price after the discount, never lower than `min_price`
+  pub(crate) fn discounted_price(&self, discount_amount: u128) -> u128 {
+    self.price.saturating_sub(discount_amount).max(self.min_price)
+  }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(crate = "near_sdk::serde")]
 pub struct CouponKey {
-    product_id: String,
-    code: String, 
-    seller: AccountId
+    pub(crate) product_id: String,
+    pub(crate) code: String,
+    pub(crate) seller: AccountId
   }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+impl CouponKey {
+  // redemptions and stats leave the seller out, so they outlive the coupon and a transfer of the product
+  pub(crate) fn history_key(&self) -> (String, String) {
+    (self.product_id.clone(), self.code.clone())
+  }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Coupon {
-  code: String,
-  product_id: String,
-  discount_amount: u128, 
-  allowed_uses: u128, // if allowed_uses = 0 => coupon is invalid
-  seller: AccountId 
+  pub(crate) code: String,
+  pub(crate) product_id: String,
+  pub(crate) discount_amount: u128,
+  pub(crate) allowed_uses: u128, // if allowed_uses = 0 => coupon is invalid
+  pub(crate) seller: AccountId,
+  pub(crate) is_active: bool, // seller can pause a coupon without deleting it
+  pub(crate) nft_contract_id: Option<AccountId>, // only holders of a token from this contract can use the coupon
+  pub(crate) is_hashed: bool, // `code` is the sha256 hex hash of the code buyers type
+}
+
+impl Coupon {
+  fn key(&self) -> CouponKey {
+    CouponKey {
+      product_id: self.product_id.clone(),
+      code: self.code.clone(),
+      seller: self.seller.clone(),
+    }
+  }
 }
 
 enum ETrackingType {
-  ReviewProduct,
-  BuyerProduct,
+  ReviewProduct = 1,
+  BuyerProduct = 2,
 }
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct TrackingKey {
-    product_id: String,
-    reviewer: AccountId,
-    tracking_type: u8 // review_product: 1, buy_product: 2
+    pub(crate) product_id: String,
+    pub(crate) reviewer: AccountId,
+    pub(crate) tracking_type: u8 // review_product: 1, buy_product: 2
 }
 
 
@@ -60,18 +114,97 @@ pub struct TrackingKey {
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone )]
 #[serde(crate = "near_sdk::serde")]
 pub struct Review {
-  product_id: String,
-  reviewer: AccountId, 
-  content: String,
-  star: u64
+  pub(crate) product_id: String,
+  pub(crate) reviewer: AccountId,
+  pub(crate) content: String,
+  pub(crate) star: u64,
+  pub(crate) verified: bool, // the reviewer bought the product before writing the review
+  pub(crate) edited_at: Option<U64>, // block timestamp of the last edit
+  pub(crate) reply: Option<ReviewReply>,
+  pub(crate) created_at: U64,
+  pub(crate) helpful: u64, // votes of other accounts
+  pub(crate) unhelpful: u64,
+  pub(crate) hidden: Option<ReviewModeration>, // set by a moderator, hidden reviews only show up in `get_my_reviews`
+  pub(crate) media: Vec<ReviewMedia>, // attachments, see `validate_review_media`
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ReviewSort {
+  MostHelpful,
+  Newest,
+  Star, // highest star first
+}
+
+// where a review sits in one of the sorted views, reviews with the lowest rank come first
+pub(crate) type ReviewRank = (u64, u64, AccountId);
+
+impl ReviewSort {
+  const ALL: [ReviewSort; 3] = [ReviewSort::MostHelpful, ReviewSort::Newest, ReviewSort::Star];
+
+  fn rank(self, review: &Review) -> ReviewRank {
+    let (first, second) = match self {
+      ReviewSort::MostHelpful => (u64::MAX - review.helpful, review.unhelpful),
+      ReviewSort::Newest => (u64::MAX - review.created_at.0, 0),
+      ReviewSort::Star => (u64::MAX - review.star, u64::MAX - review.created_at.0),
+    };
+    (first, second, review.reviewer.clone())
+  }
+}
+
+// the seller's answer to a review, one per review
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone )]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReviewReply {
+  pub(crate) content: String,
+  pub(crate) replied_at: U64, // block timestamp of the first reply
+  pub(crate) edited_at: Option<U64>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone )]
 #[serde(crate = "near_sdk::serde")]
 pub struct PurchaseInfo {
-  product_id: String,
-  origin_price: u128,
-  profit_price: u128,
+  pub(crate) product_id: String,
+  pub(crate) origin_price: u128,
+  pub(crate) profit_price: u128,
+  pub(crate) receipt_id: Option<U64>, // see `get_receipt`
+}
+
+// result of one code in `create_coupons`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponBatchResult {
+  pub code: String,
+  pub created: bool,
+  pub error: Option<ErrorJson>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponRedemption {
+  pub buyer: AccountId,
+  pub product_id: String,
+  pub discount_amount: U128,
+  pub paid_price: U128,
+  pub timestamp: U64,
+}
+
+// running totals of a coupon, updated on every redemption
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct CouponStats {
+  redemptions: u64,
+  total_discount: u128,
+  revenue: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponStatsJson {
+  pub product_id: String,
+  pub code: String,
+  pub redemptions: U64,
+  pub total_discount: U128,
+  pub revenue: U128,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -79,367 +212,1152 @@ pub struct PurchaseInfo {
 pub struct ProductJson {
   id: String,
   name: String,
-  description: String, 
-  img: String, 
+  description: String,
+  img: String,
   is_active: bool,
   // price: U128,
   seller: AccountId,
+  is_archived: bool,
+  category: Option<String>,
+  tags: Vec<String>,
+  rating: ProductRatingJson,
 }
 
-#[near_bindgen]
-impl Contract {
-  
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouponJson {
+  pub product_id: String,
+  pub code: String,
+  pub seller: AccountId,
+  pub discount_amount: U128,
+  pub remaining_uses: U128,
+  pub is_active: bool,
+  pub nft_contract_id: Option<AccountId>,
+  pub is_hashed: bool,
+}
 
-  #[payable] // Buy the product
-  pub fn buy_product(&mut self, product_id: String, has_coupon: bool, coupon_code: String) -> bool {
-    
-    assert!( self.products.get(&product_id).is_some() == true, "Can't find the product with id {}", product_id);
+impl From<Coupon> for CouponJson {
+  fn from(coupon: Coupon) -> Self {
+    CouponJson {
+      product_id: coupon.product_id,
+      code: coupon.code,
+      seller: coupon.seller,
+      discount_amount: U128(coupon.discount_amount),
+      remaining_uses: U128(coupon.allowed_uses),
+      is_active: coupon.is_active,
+      nft_contract_id: coupon.nft_contract_id,
+      is_hashed: coupon.is_hashed,
+    }
+  }
+}
 
+#[near_bindgen]
+impl Contract {
 
-    let buyer: AccountId = env::predecessor_account_id(); 
 
-    let product = self.products.get(&product_id).unwrap();
+  #[payable] // Buy the product
+  pub fn buy_product(&mut self, product_id: String, has_coupon: bool, coupon_code: String) -> PromiseOrValue<bool> {
 
-    let mut purchased_price: u128 = product.price.clone();
+    let buyer: AccountId = env::predecessor_account_id();
 
-    assert!( product.seller != buyer, "You can't buy your own product");
-    assert!( product.is_active == true, "Product is in-active");
+    let coupon_code = if has_coupon { Some(coupon_code) } else { None };
+    let (product, coupon) = self.validate_purchase(&buyer, &product_id, coupon_code)
+      .unwrap_or_else(|error| error.panic());
 
-    if let Some(tracking) = self.tracking.get(&TrackingKey { 
-      product_id: product.id.clone(),
-      reviewer: env::predecessor_account_id(), 
-      tracking_type: 2
-      }) {
-        assert!( tracking == false, "You already bought this product");
-      } 
-
-    log!("{} buying product {} ", buyer, product.name);
-
-    
-
-    // no coupon 
-    if has_coupon == false {
-        // Buyer sends Near to seller 
-        Promise::new(product.seller).transfer(product.price);
-    }else {
-    // has coupon
-      let current_coupon_key = CouponKey { 
-        product_id: product.id.clone(), 
-        code: coupon_code,
-        seller: product.seller };
-       assert!( self.coupons.get(&current_coupon_key).is_some(), "This coupon is not exist");
-       // get coupon details 
-       let coupon = self.coupons.get(&current_coupon_key).unwrap();
-       assert!( coupon.allowed_uses > 0, "This coupon's allowed uses is 0");
-       // get new price
-       let new_discount_price = product.price - coupon.discount_amount;
-        purchased_price = new_discount_price.clone();
-       
-        let seller = coupon.seller.clone();
-        // coupon's seller and product's seller are the same 
-        Promise::new(seller.clone()).transfer(new_discount_price);
+    // coupons for NFT holders are only applied once the NFT contract confirms the buyer holds a token
+    if let Some(coupon) = coupon.as_ref().filter(|coupon| coupon.nft_contract_id.is_some()) {
+      let price = self.purchase_price(&product, Some(coupon));
+      require(env::attached_deposit() >= price, PaydiiError::InsufficientDeposit { required: price, attached: env::attached_deposit() });
 
-        let updated_coupon = Coupon {
-          code: coupon.code,
-          product_id: coupon.product_id,
-          discount_amount: coupon.discount_amount,
-          allowed_uses: coupon.allowed_uses - 1, 
-          seller: coupon.seller };
-
-        // update coupon 
-        self.coupons.insert(&CouponKey {
-          product_id: updated_coupon.product_id.clone(),
-          code: updated_coupon.code.clone(),
-          seller: seller.clone(),
-        }, &updated_coupon);
-    }
-
-    let new_purchase_info = PurchaseInfo { 
-      product_id: product.id,
-      origin_price: product.price, 
-      profit_price: purchased_price };
-    
-    let product_id_copy = new_purchase_info.product_id.clone();
-    
-    // update list of purchased products
-    if ( self.buyers.get(&env::predecessor_account_id()).is_none() == true) {
-        self.buyers.insert(&env::predecessor_account_id(),&vec![new_purchase_info]);
-    }else {
-      let mut current_purchased_info_list = self.buyers.get(&env::predecessor_account_id()).unwrap();
-      current_purchased_info_list.push(new_purchase_info);
-      self.buyers.insert(&env::predecessor_account_id(), &current_purchased_info_list);
-    } 
-    
-    // create product for the first time
-    if self.buyer_addresses.get(&product_id.clone()) == None {
-      self.buyer_addresses.insert(&product_id.clone(),&vec![env::predecessor_account_id()]);
-    } else {
-      let mut current_buyer_ids = self.buyer_addresses.get(&product_id.clone()).unwrap();
-      current_buyer_ids.push(env::predecessor_account_id());
-      self.buyer_addresses.insert(&product_id.clone(),&current_buyer_ids);
+      let nft_contract_id = coupon.nft_contract_id.clone().unwrap();
+      return self.check_nft_holder(nft_contract_id, buyer, product_id, coupon.code.clone(), env::attached_deposit()).into();
     }
 
-    self.tracking.insert(&TrackingKey { 
-      product_id: product_id_copy,
-      reviewer: env::predecessor_account_id(), 
-      tracking_type: 2 },&true);
-    
-    true 
+    PromiseOrValue::Value(self.complete_purchase(buyer, product, coupon, env::attached_deposit()))
   }
 
 
+  #[payable] // the seller pays for the product's storage, the rest of the deposit is refunded
   pub fn create_product(&mut self, id: String, name: String, price: U128, description: String, img: String, is_active: bool) -> Product {
-    
-    assert!( self.products.get(&id.clone()).is_none() == true, "This product is is exists already");
+
+    self.assert_not_paused(PausableFeature::ProductCreation);
+    let initial_storage = env::storage_usage();
+    require(self.products.get(&id).is_none(), PaydiiError::ProductExists(id.clone()));
 
     let seller: AccountId = env::predecessor_account_id();
-    
+
     let new_product = Product {
-      id: String::from(id),
-      name: String::from(name),
+      id,
+      name,
       // price: price.parse::<u128>().unwrap(),
       price: u128::from(price),
-      description: String::from(description),
-      img: String::from(img),
-      is_active: is_active,
-      seller: seller
+      description,
+      img,
+      is_active,
+      seller,
+      min_price: 0,
+      is_archived: false,
+      category: None,
+      tags: vec![]
     };
-    self.products.insert(&new_product.id.clone(),&new_product);
+    self.products.insert(&new_product.id.clone(), &new_product.clone().into());
 
-    // add product by seller 
-    // create product for the first time
-    if self.products_by_sellers.get(&env::predecessor_account_id()) == None {
-      let new_product_ids = vec![new_product.id.clone()];
-      self.products_by_sellers.insert(&env::predecessor_account_id(),&new_product_ids);
+    // add product by seller
+    set_insert(&mut self.products_by_sellers, StorageKey::ProductBySellerInner, &new_product.seller, &new_product.id);
+    self.product_list.insert(&new_product.id);
+    self.record_seller(&new_product.seller);
 
-    // already has at least 1 product
-    } else {
-      let mut current_product_ids = self.products_by_sellers.get(&env::predecessor_account_id()).unwrap();
-      current_product_ids.push(new_product.id.clone());
-      self.products_by_sellers.insert(&env::predecessor_account_id(),&current_product_ids);
-    }
-    self.product_list.push(new_product.id.clone());
-  
+    self.charge_storage(&new_product.seller, &new_product.id, initial_storage);
+    emit_event("product_created", ProductEvent::from(&new_product));
     new_product
   }
 
+  #[payable] // a longer name or description is charged to the seller, a shorter one refunded
   pub fn update_product(&mut self, id: String, name: String, price: U128, description: String, img: String, is_active: bool) -> Product {
-    
-    assert!( self.products.get(&id.clone()).is_some() == true, "Product with this id is not exist");
-    let product: Product = self.products.get(&id.clone()).unwrap();
-    assert!( product.seller == env::predecessor_account_id(), "You are not the product's owner");
+
+    let initial_storage = env::storage_usage();
+    let product = self.expect_product(&id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(!product.is_archived, PaydiiError::ProductArchived);
     let updated_product = Product {
       id: product.id,
-      name: String::from(name),
+      name,
       price: u128::from(price),
-      description: String::from(description),
-      img: String::from(img),
-      is_active: is_active,
-      seller: product.seller
+      description,
+      img,
+      is_active,
+      seller: product.seller,
+      min_price: product.min_price,
+      is_archived: product.is_archived,
+      category: product.category,
+      tags: product.tags
     };
-    self.products.insert(&id.clone(),&updated_product);
+    require(updated_product.price >= updated_product.min_price,
+      PaydiiError::PriceBelowMinPrice { price: updated_product.price, min_price: updated_product.min_price });
+    self.assert_coupons_fit_price(&updated_product);
+    self.products.insert(&id, &updated_product.clone().into());
+    self.settle_storage(&updated_product.seller, &id, initial_storage);
+    emit_event("product_updated", ProductEvent::from(&updated_product));
 
-    // add product by seller 
     updated_product
   }
 
-  pub fn create_coupon(&mut self, product_id: String, code: String, allowed_uses: U128, discount_amount: U128) -> Coupon { 
-    assert!( self.products.get(&product_id.clone()).is_some() == true, "Product with this id is not exist");
-    let product: Product = self.products.get(&product_id.clone()).unwrap();
-    assert!( product.seller == env::predecessor_account_id(), "You are not the product's owner");
+  // set the lowest price coupons can bring the product down to
+  pub fn set_product_min_price(&mut self, product_id: String, min_price: U128) -> Product {
+    let mut product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(min_price.0 <= product.price, PaydiiError::MinPriceAbovePrice { min_price: min_price.0, price: product.price });
+
+    product.min_price = min_price.0;
+    self.assert_coupons_fit_price(&product);
+    self.products.insert(&product_id, &product.clone().into());
+    emit_event("product_updated", ProductEvent::from(&product));
+    product
+  }
 
-    assert!( self.coupons.get(&CouponKey {
+  #[payable] // the seller pays for the coupon's storage, like for the product's
+  pub fn create_coupon(&mut self, product_id: String, code: String, allowed_uses: U128, discount_amount: U128, nft_contract_id: Option<AccountId>) -> Coupon {
+    let initial_storage = env::storage_usage();
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(!product.is_archived, PaydiiError::ProductArchived);
+
+    require(self.coupons.get(&CouponKey {
       product_id: product_id.clone(),
       code: code.clone(),
       seller: product.seller.clone(),
-    }).is_none() == true, "This coupon for this product is already exist");
+    }).is_none(), PaydiiError::CouponExists);
+    require(code.len() <= MAX_COUPON_CODE_LENGTH, PaydiiError::CouponCodeTooLong { max_length: MAX_COUPON_CODE_LENGTH });
+    assert_discount_fits_price(&product, u128::from(discount_amount));
+    if let Some(nft_contract_id) = &nft_contract_id {
+      require(self.nft_contract_ids.contains(nft_contract_id), PaydiiError::NftContractNotAllowed(nft_contract_id.clone()));
+    }
       let new_coupon = Coupon {
-        product_id: product_id,
-        code: code,
+        product_id,
+        code,
         discount_amount: u128::from(discount_amount),
-        allowed_uses: u128::from(allowed_uses), 
-        seller: product.seller
+        allowed_uses: u128::from(allowed_uses),
+        seller: product.seller,
+        is_active: true,
+        nft_contract_id,
+        is_hashed: false
+      };
+      self.coupons.insert(&new_coupon.key(), &new_coupon.clone().into());
+
+      set_insert(&mut self.coupons_by_seller, StorageKey::CouponsBySellerInner, &new_coupon.seller, &new_coupon.key());
+      set_insert(&mut self.coupons_by_product, StorageKey::CouponsByProductInner, &new_coupon.product_id, &new_coupon.key());
+      self.add_coupon_discount(&new_coupon.product_id, new_coupon.discount_amount, 1);
+      self.charge_storage(&new_coupon.seller, &new_coupon.product_id, initial_storage);
+      emit_event("coupon_created", CouponJson::from(new_coupon.clone()));
+
+      new_coupon
+  }
+
+  // create many coupons sharing the same settings, `codes_are_hashes` stores sha256 hex hashes
+  // of the codes so the plain codes never show up on chain
+  #[payable]
+  pub fn create_coupons(&mut self, product_id: String, codes: Vec<String>, codes_are_hashes: bool, allowed_uses: U128, discount_amount: U128, nft_contract_id: Option<AccountId>) -> Vec<CouponBatchResult> {
+    let initial_storage = env::storage_usage();
+    require(!codes.is_empty(), PaydiiError::EmptyCouponBatch);
+    require(codes.len() <= MAX_COUPON_BATCH, PaydiiError::CouponBatchTooLarge { max: MAX_COUPON_BATCH });
+
+    let product: Product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(!product.is_archived, PaydiiError::ProductArchived);
+    assert_discount_fits_price(&product, u128::from(discount_amount));
+    if let Some(nft_contract_id) = &nft_contract_id {
+      require(self.nft_contract_ids.contains(nft_contract_id), PaydiiError::NftContractNotAllowed(nft_contract_id.clone()));
+    }
+
+    let mut current_coupons = self.coupons_by_seller.get(&product.seller)
+      .unwrap_or_else(|| UnorderedSet::new(nested_prefix(StorageKey::CouponsBySellerInner, &product.seller)));
+    let mut product_coupons = self.coupons_by_product.get(&product_id)
+      .unwrap_or_else(|| UnorderedSet::new(nested_prefix(StorageKey::CouponsByProductInner, &product_id)));
+    let mut results = Vec::with_capacity(codes.len());
+    let mut created = vec![];
+
+    for code in codes {
+      let key = CouponKey {
+        product_id: product_id.clone(),
+        code: code.clone(),
+        seller: product.seller.clone(),
       };
-      self.coupons.insert(&CouponKey {
-        product_id: new_coupon.product_id.clone(),
-        code: new_coupon.code.clone(),
-        seller: new_coupon.seller.clone(),
-      }, &new_coupon);
-      
-      // create coupon for the first time
-      if self.coupons_by_seller.get(&env::predecessor_account_id()).is_none() == true {
-        self.coupons_by_seller.insert(&env::predecessor_account_id(),&vec![CouponKey {
-          product_id: new_coupon.product_id.clone(),
-          code: new_coupon.code.clone(),
-          seller: new_coupon.seller.clone(),
-        }]);
+
+      let error = if code.is_empty() {
+        Some(PaydiiError::EmptyCouponCode)
+      } else if code.len() > MAX_COUPON_CODE_LENGTH {
+        Some(PaydiiError::CouponCodeTooLong { max_length: MAX_COUPON_CODE_LENGTH })
+      } else if codes_are_hashes && !is_code_hash(&code) {
+        Some(PaydiiError::InvalidCouponHash)
+      } else if self.coupons.get(&key).is_some() {
+        Some(PaydiiError::CouponExists)
       } else {
-        let mut current_coupons = self.coupons_by_seller.get(&env::predecessor_account_id()).unwrap();
-        current_coupons.push(CouponKey {
-          product_id: new_coupon.product_id.clone(),
-          code: new_coupon.code.clone(),
-          seller: new_coupon.seller.clone(),
-        });
-        self.coupons_by_seller.insert(&env::predecessor_account_id(), &current_coupons);
+        None
+      };
+
+      if error.is_none() {
+        let new_coupon = Coupon {
+          product_id: product_id.clone(),
+          code: code.clone(),
+          discount_amount: u128::from(discount_amount),
+          allowed_uses: u128::from(allowed_uses),
+          seller: product.seller.clone(),
+          is_active: true,
+          nft_contract_id: nft_contract_id.clone(),
+          is_hashed: codes_are_hashes
+        };
+        self.coupons.insert(&key, &new_coupon.clone().into());
+        current_coupons.insert(&key);
+        product_coupons.insert(&key);
+        created.push(new_coupon.code);
       }
 
-      new_coupon
+      results.push(CouponBatchResult {
+        code,
+        created: error.is_none(),
+        error: error.map(ErrorJson::from),
+      });
+    }
+
+    if !created.is_empty() {
+      self.coupons_by_seller.insert(&product.seller, &current_coupons);
+      self.coupons_by_product.insert(&product_id, &product_coupons);
+      self.add_coupon_discount(&product_id, u128::from(discount_amount), created.len() as u64);
+      emit_event("coupons_created", CouponsCreatedEvent {
+        product_id: product_id.clone(),
+        seller: product.seller.clone(),
+        codes: created,
+        discount_amount,
+        allowed_uses,
+        nft_contract_id,
+        is_hashed: codes_are_hashes,
+      });
+    }
+    self.settle_storage(&product.seller, &product_id, initial_storage);
+    results
   }
 
-  pub fn update_coupon(&mut self, product_id: String, code: String, allowed_uses: U128, discount_amount: U128) -> Coupon { 
-    assert!( self.products.get(&product_id.clone()).is_some() == true, "Product with this id is not exist");
-    
-    let product: Product = self.products.get(&product_id.clone()).unwrap();
+  pub fn update_coupon(&mut self, product_id: String, code: String, allowed_uses: U128, discount_amount: U128) -> Coupon {
+    let product = self.expect_product(&product_id);
 
-    assert!( product.seller == env::predecessor_account_id(), "You are not the product's owner");
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
 
-    assert!( self.coupons.get(&CouponKey {
+    let current_coupon = self.coupons.get(&CouponKey {
       product_id: product_id.clone(),
       code: code.clone(),
       seller: product.seller.clone(),
-    }).is_some() == true, "This coupon for this product is already exist");   
+    }).map(Coupon::from).unwrap_or_else(|| PaydiiError::CouponNotFound.panic());
+    assert_discount_fits_price(&product, u128::from(discount_amount));
       let updated_coupon = Coupon {
-        product_id: product_id,
-        code: code,
+        product_id,
+        code,
         discount_amount: u128::from(discount_amount),
-        allowed_uses: u128::from(allowed_uses), 
-        seller: product.seller
+        allowed_uses: u128::from(allowed_uses),
+        seller: product.seller,
+        is_active: current_coupon.is_active,
+        nft_contract_id: current_coupon.nft_contract_id,
+        is_hashed: current_coupon.is_hashed
       };
-      self.coupons.insert(&CouponKey {
-        product_id: updated_coupon.product_id.clone(),
-        code: updated_coupon.code.clone(),
-        seller: updated_coupon.seller.clone(),
-      }, &updated_coupon);
+      self.coupons.insert(&updated_coupon.key(), &updated_coupon.clone().into());
+      if updated_coupon.discount_amount != current_coupon.discount_amount {
+        self.remove_coupon_discount(&updated_coupon.product_id, current_coupon.discount_amount, 1);
+        self.add_coupon_discount(&updated_coupon.product_id, updated_coupon.discount_amount, 1);
+      }
+      emit_event("coupon_updated", CouponJson::from(updated_coupon.clone()));
       updated_coupon
   }
 
-  pub fn add_review(&mut self, product_id: String, content: String, star: U64) -> bool { 
-    assert!( self.products.get(&product_id.clone()).is_some() == true, "Product with this id is not exist");
-    let product: Product = self.products.get(&product_id.clone()).unwrap();
-    assert!( product.seller != env::predecessor_account_id(), "You can't review your own product");
+  // turn a coupon on or off, the coupon keeps its remaining uses
+  pub fn set_coupon_active(&mut self, product_id: String, code: String, is_active: bool) -> Coupon {
+    let seller = env::predecessor_account_id();
+    let key = CouponKey { product_id, code, seller };
+    let mut coupon = self.coupons.get(&key).map(Coupon::from).unwrap_or_else(|| PaydiiError::CouponNotFound.panic());
+
+    coupon.is_active = is_active;
+    self.coupons.insert(&key, &coupon.clone().into());
+    emit_event("coupon_updated", CouponJson::from(coupon.clone()));
+    coupon
+  }
+
+  // remove a coupon from `coupons` and the coupon indexes
+  pub fn delete_coupon(&mut self, product_id: String, code: String) -> bool {
+    let initial_storage = env::storage_usage();
+    let seller = env::predecessor_account_id();
+    let key = CouponKey { product_id: product_id.clone(), code, seller: seller.clone() };
+    require(self.coupons.get(&key).is_some(), PaydiiError::CouponNotFound);
+
+    self.remove_coupons(&product_id, &seller, vec![key]);
+    self.refund_storage(&seller, &product_id, initial_storage);
+    true
+  }
+
+  // remove up to MAX_COUPON_BATCH coupons of a product, returns how many are left.
+  // Call it until it returns 0 to clear the coupons of an archived product
+  pub fn delete_product_coupons(&mut self, product_id: String) -> U64 {
+    let initial_storage = env::storage_usage();
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+
+    let keys: Vec<CouponKey> = self.coupons_by_product.get(&product_id)
+      .map_or(vec![], |coupon_keys| coupon_keys.iter().take(MAX_COUPON_BATCH).collect());
+    self.remove_coupons(&product_id, &product.seller, keys);
+
+    self.refund_storage(&product.seller, &product_id, initial_storage);
+    U64(set_len(&self.coupons_by_product, &product_id))
+  }
+
+  // remove a product that was never sold, with its coupons and reviews. Products with more than
+  // MAX_COUPON_BATCH coupons need `delete_product_coupons` first
+  pub fn delete_product(&mut self, product_id: String) -> bool {
+    let initial_storage = env::storage_usage();
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(set_len(&self.buyer_addresses, &product_id) == 0, PaydiiError::ProductHasBuyers(product_id.clone()));
+    require(set_len(&self.coupons_by_product, &product_id) <= MAX_COUPON_BATCH as u64, PaydiiError::ProductHasCoupons { max: MAX_COUPON_BATCH });
+
+    self.products.remove(&product_id);
+    emit_event("product_deleted", ProductDeletedEvent { id: product_id.clone(), seller: product.seller.clone() });
+    self.remove_product_from_listings(&product);
+    self.remove_coupons(&product_id, &product.seller, self.product_coupon_keys(&product_id));
+    self.promotions.remove(&PromotionScope::Product(product_id.clone()));
+    self.product_transfers.remove(&product_id);
+
+    if let Some(mut product_reviews) = self.reviews.remove(&product_id) {
+      for reviewer in product_reviews.keys_as_vector().to_vec() {
+        set_remove(&mut self.my_reviews, &reviewer, &product_id);
+        self.clear_review_votes(&product_id, &reviewer);
+        self.clear_review_reports(&product_id, &reviewer);
+        self.tracking.remove(&TrackingKey {
+          product_id: product_id.clone(),
+          reviewer,
+          tracking_type: ETrackingType::ReviewProduct as u8
+        });
+      }
+      product_reviews.clear();
+    }
+    for sort_by in ReviewSort::ALL {
+      tree_clear(&mut self.sorted_reviews, &(product_id.clone(), sort_by));
+    }
+    self.remove_product_rating(&product);
+    self.hidden_review_counts.remove(&product_id);
+
+    self.refund_storage(&product.seller, &product_id, initial_storage);
+    self.clear_product_storage(&product.seller, &product_id);
+    true
+  }
+
+  // hide a sold product from listings, buyers keep their purchase and reviews stay readable.
+  // Its coupons can no longer be redeemed, `delete_product_coupons` clears them in batches
+  pub fn archive_product(&mut self, product_id: String) -> Product {
+    let initial_storage = env::storage_usage();
+    let mut product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(!product.is_archived, PaydiiError::ProductArchived);
+
+    product.is_archived = true;
+    product.is_active = false;
+    self.products.insert(&product_id, &product.clone().into());
+    self.remove_product_from_listings(&product);
+    self.promotions.remove(&PromotionScope::Product(product_id.clone()));
+    self.product_transfers.remove(&product_id);
+
+    self.refund_storage(&product.seller, &product_id, initial_storage);
+    emit_event("product_updated", ProductEvent::from(&product));
+    product
+  }
+
+  // first step of handing a product to another seller, the new seller has to accept it
+  pub fn propose_product_transfer(&mut self, product_id: String, new_seller: AccountId) -> bool {
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    require(product.seller != new_seller, PaydiiError::AlreadyOwner);
+    self.assert_coupons_can_transfer(&product_id);
+
+    self.product_transfers.insert(&product_id, &new_seller);
+    true
+  }
+
+  pub fn cancel_product_transfer(&mut self, product_id: String) -> bool {
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    self.product_transfers.remove(&product_id).is_some()
+  }
+
+  // second step, called by the new seller. Buyers keep their access and coupons move along
+  pub fn accept_product_transfer(&mut self, product_id: String) -> Product {
+    let new_seller = env::predecessor_account_id();
+    require(self.product_transfers.get(&product_id) == Some(new_seller.clone()), PaydiiError::TransferNotProposed(product_id.clone()));
+    self.product_transfers.remove(&product_id);
+    // coupons may have been created since the proposal
+    self.assert_coupons_can_transfer(&product_id);
+
+    let mut product = self.expect_product(&product_id);
+    let old_seller = product.seller.clone();
+    product.seller = new_seller.clone();
+    self.products.insert(&product_id, &product.clone().into());
+    emit_event("product_updated", ProductEvent::from(&product));
+    self.record_seller(&new_seller);
+    self.move_product_rating(&product_id, &old_seller, &new_seller);
+    self.move_product_storage(&product_id, &old_seller, &new_seller);
+
+    if !product.is_archived {
+      set_remove(&mut self.products_by_sellers, &old_seller, &product_id);
+      set_insert(&mut self.products_by_sellers, StorageKey::ProductBySellerInner, &new_seller, &product_id);
+    }
+
+    // coupon keys embed the seller, so every coupon of the product is stored again under the new seller.
+    // The index sets are read and written once instead of once per coupon
+    if let Some(mut product_coupons) = self.coupons_by_product.get(&product_id) {
+      let mut old_coupons = self.coupons_by_seller.get(&old_seller)
+        .unwrap_or_else(|| UnorderedSet::new(nested_prefix(StorageKey::CouponsBySellerInner, &old_seller)));
+      let mut new_coupons = self.coupons_by_seller.get(&new_seller)
+        .unwrap_or_else(|| UnorderedSet::new(nested_prefix(StorageKey::CouponsBySellerInner, &new_seller)));
+
+      for old_key in product_coupons.to_vec() {
+        let new_key = CouponKey { seller: new_seller.clone(), ..old_key.clone() };
+        old_coupons.remove(&old_key);
+        product_coupons.remove(&old_key);
+        if let Some(coupon) = self.coupons.remove(&old_key).map(Coupon::from) {
+          let moved_coupon = Coupon { seller: new_seller.clone(), ..coupon };
+          self.coupons.insert(&new_key, &moved_coupon.into());
+          new_coupons.insert(&new_key);
+          product_coupons.insert(&new_key);
+        }
+      }
+
+      if old_coupons.is_empty() {
+        self.coupons_by_seller.remove(&old_seller);
+      } else {
+        self.coupons_by_seller.insert(&old_seller, &old_coupons);
+      }
+      if !new_coupons.is_empty() {
+        self.coupons_by_seller.insert(&new_seller, &new_coupons);
+      }
+      if product_coupons.is_empty() {
+        self.coupons_by_product.remove(&product_id);
+      } else {
+        self.coupons_by_product.insert(&product_id, &product_coupons);
+        emit_event("coupons_transferred", CouponsTransferredEvent {
+          product_id: product_id.clone(),
+          old_seller: old_seller.clone(),
+          new_seller: new_seller.clone(),
+          count: U64(product_coupons.len()),
+        });
+      }
+    }
+
+    if let Some(mut promotion) = self.promotions.get(&PromotionScope::Product(product_id.clone())) {
+      promotion.seller = new_seller;
+      self.promotions.insert(&PromotionScope::Product(product_id), &promotion);
+    }
+
+    product
+  }
+
+  pub fn add_review(&mut self, product_id: String, content: String, star: U64, media: Option<Vec<ReviewMedia>>) -> bool {
+    self.assert_not_paused(PausableFeature::Reviews);
+    let product = self.expect_product(&product_id);
+    require(product.seller != env::predecessor_account_id(), PaydiiError::OwnProduct);
+    require((MIN_STAR..=MAX_STAR).contains(&star.0), PaydiiError::InvalidStar);
+    let media = media.unwrap_or_default();
+    validate_review_media(&media);
 
     let new_review = Review {
       product_id: product.id.clone(),
       reviewer: env::predecessor_account_id(),
       star: u64::from(star),
-      content: content
+      content,
+      verified: self.has_purchased(&env::predecessor_account_id(), &product.id),
+      edited_at: None,
+      reply: None,
+      created_at: U64(env::block_timestamp()),
+      helpful: 0,
+      unhelpful: 0,
+      hidden: None,
+      media,
     };
 
-    if let Some(tracking) = self.tracking.get(&TrackingKey { 
+    if let Some(tracking) = self.tracking.get(&TrackingKey {
       product_id: product.id.clone(),
-      reviewer: env::predecessor_account_id(), 
-      tracking_type: 1
+      reviewer: env::predecessor_account_id(),
+      tracking_type: ETrackingType::ReviewProduct as u8
       }) {
-        assert!( tracking == false, "You already reviewd this product");
-      } 
-
-    
-    let review_copy = new_review.clone();
-
-    // if product has the first review
-    if self.reviews.get(&product_id.clone()).is_none() == true {
-      self.reviews.insert(&product_id.clone(),&vec![new_review.clone()]);
-    }else {
-      let mut current_reviews = self.reviews.get(&product_id.clone()).unwrap();
-      current_reviews.push(new_review);
-      self.reviews.insert(&product.id.clone(),&current_reviews);
-    }  
+        require(!tracking, PaydiiError::AlreadyReviewed);
+      }
 
-    
 
-    // if user has review the first product 
-    if self.my_reviews.get(&env::predecessor_account_id()).is_none() == true {
-      self.my_reviews.insert(&env::predecessor_account_id(),&vec![review_copy]);
-    }else {
-      let mut current_reviews = self.my_reviews.get(&env::predecessor_account_id()).unwrap();
-      current_reviews.push(review_copy);
-      self.my_reviews.insert(&env::predecessor_account_id(),&current_reviews);
-    }
+    // the review is stored once per product, `my_reviews` only keeps the product ids
+    map_insert(&mut self.reviews, StorageKey::ReviewsInner, &product_id, &new_review.reviewer, &new_review.clone().into());
+    set_insert(&mut self.my_reviews, StorageKey::MyReviewsInner, &new_review.reviewer, &product_id);
+    self.reindex_review(&product_id, None, Some(&new_review));
+    self.add_to_rating(&product_id, new_review.star);
 
     // update review tracking of
-    self.tracking.insert(&TrackingKey { 
+    self.tracking.insert(&TrackingKey {
         product_id: product.id.clone(),
-        reviewer: env::predecessor_account_id(), 
-        tracking_type: 1
+        reviewer: env::predecessor_account_id(),
+        tracking_type: ETrackingType::ReviewProduct as u8
     }, &true);
+    emit_event("review_added", new_review);
+
+    true
+  }
+
+  // the reviewer can change the text, star and attachments of their review, `media: None` keeps the attachments
+  pub fn update_review(&mut self, product_id: String, content: String, star: U64, media: Option<Vec<ReviewMedia>>) -> Review {
+    self.assert_not_paused(PausableFeature::Reviews);
+    require((MIN_STAR..=MAX_STAR).contains(&star.0), PaydiiError::InvalidStar);
+    if let Some(media) = &media {
+      validate_review_media(media);
+    }
+    let reviewer = env::predecessor_account_id();
+    let mut review = self.expect_review(&product_id, &reviewer);
+    let old_review = review.clone();
+
+    if review.hidden.is_none() {
+      self.remove_from_rating(&product_id, review.star);
+      self.add_to_rating(&product_id, star.0);
+    }
 
+    review.content = content;
+    review.star = star.0;
+    if let Some(media) = media {
+      review.media = media;
+    }
+    review.edited_at = Some(U64(env::block_timestamp()));
+    map_insert(&mut self.reviews, StorageKey::ReviewsInner, &product_id, &reviewer, &review.clone().into());
+    self.reindex_review(&product_id, Some(&old_review), Some(&review));
+    emit_event("review_updated", review.clone());
+    review
+  }
+
+  // the product's seller answers a review, replying again replaces the answer
+  pub fn reply_to_review(&mut self, product_id: String, reviewer: AccountId, content: String) -> Review {
+    self.assert_not_paused(PausableFeature::Reviews);
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    let mut review = self.expect_review(&product_id, &reviewer);
+
+    let now = U64(env::block_timestamp());
+    review.reply = Some(match review.reply {
+      Some(reply) => ReviewReply { content, edited_at: Some(now), ..reply },
+      None => ReviewReply { content, replied_at: now, edited_at: None },
+    });
+    map_insert(&mut self.reviews, StorageKey::ReviewsInner, &product_id, &reviewer, &review.clone().into());
+    emit_event("review_replied", review.clone());
+    review
+  }
+
+  // one vote per account and review, the reviewer can't vote on their own review
+  pub fn vote_review(&mut self, product_id: String, reviewer: AccountId, helpful: bool) -> Review {
+    self.assert_not_paused(PausableFeature::Reviews);
+    let voter = env::predecessor_account_id();
+    require(voter != reviewer, PaydiiError::OwnReview);
+    let mut review = self.expect_review(&product_id, &reviewer);
+    require(review.hidden.is_none(), PaydiiError::ReviewHidden);
+
+    let review_key = (product_id.clone(), reviewer.clone());
+    let previous_vote = map_insert(&mut self.review_votes, StorageKey::ReviewVotesInner, &review_key, &voter, &helpful);
+    require(previous_vote.is_none(), PaydiiError::AlreadyVoted);
+
+    let old_review = review.clone();
+    if helpful {
+      review.helpful += 1;
+    } else {
+      review.unhelpful += 1;
+    }
+    map_insert(&mut self.reviews, StorageKey::ReviewsInner, &product_id, &reviewer, &review.clone().into());
+    self.reindex_review(&product_id, Some(&old_review), Some(&review));
+    emit_event("review_voted", ReviewVoteEvent { product_id, reviewer, voter, helpful });
+    review
+  }
+
+  // the vote of `voter` on a review, None if they haven't voted
+  pub fn get_review_vote(&self, product_id: String, reviewer: AccountId, voter: AccountId) -> Option<bool> {
+    self.review_votes.get(&(product_id, reviewer))?.get(&voter)
+  }
+
+  // removes the caller's review, they can write a new one afterwards
+  pub fn delete_review(&mut self, product_id: String) -> bool {
+    let reviewer = env::predecessor_account_id();
+    let review = self.expect_review(&product_id, &reviewer);
+    // deleting would let the reviewer post the hidden review again
+    require(review.hidden.is_none(), PaydiiError::ReviewHidden);
+
+    map_remove(&mut self.reviews, &product_id, &reviewer);
+    set_remove(&mut self.my_reviews, &reviewer, &product_id);
+    self.reindex_review(&product_id, Some(&review), None);
+    self.clear_review_votes(&product_id, &reviewer);
+    self.clear_review_reports(&product_id, &reviewer);
+    self.remove_from_rating(&product_id, review.star);
+    self.tracking.remove(&TrackingKey {
+      product_id,
+      reviewer,
+      tracking_type: ETrackingType::ReviewProduct as u8
+    });
+    emit_event("review_deleted", review);
     true
   }
 
-  // get all products a buyer has bought so far
-  pub fn get_purchased_products_of_buyer(&self, buyer: AccountId) -> Option<Vec<PurchaseInfo>> {
-      self.buyers.get(&buyer)
+  // get products a buyer has bought so far, paginated
+  pub fn get_purchased_products_of_buyer(&self, buyer: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<PurchaseInfo> {
+    self.buyers.get(&buyer).map_or(vec![], |purchases| paginate(purchases.iter(), from_index, limit).map(PurchaseInfo::from).collect())
   }
 
-  // get all products in the app   
-  pub fn get_all_products(&self) -> Vec<String> {
-    self.product_list.clone()
+  pub fn get_purchased_products_count(&self, buyer: AccountId) -> U64 {
+    U64(vector_len(&self.buyers, &buyer))
   }
 
-  pub fn get_product(&self, product_id: String) -> Option<Product> { 
-    self.products.get(&product_id)
+  // get products in the app, paginated
+  pub fn get_all_products(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+    paginate(self.product_list.iter(), from_index, limit).collect()
   }
 
-  pub fn get_seller_product(&self, seller: AccountId) -> Option<Vec<String>> {
-    self.products_by_sellers.get(&seller.clone())
+  pub fn get_products_count(&self) -> U64 {
+    U64(self.product_list.len())
+  }
+
+  // the account a product is being transferred to, if any
+  pub fn get_product_transfer(&self, product_id: String) -> Option<AccountId> {
+    self.product_transfers.get(&product_id)
+  }
+
+  pub fn get_product(&self, product_id: String) -> Option<ProductView> {
+    self.products.get(&product_id).map(|product| self.product_view(product.into()))
+  }
+
+  pub fn get_seller_product(&self, seller: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+    self.products_by_sellers.get(&seller).map_or(vec![], |product_ids| paginate(product_ids.iter(), from_index, limit).collect())
+  }
+
+  pub fn get_seller_products_count(&self, seller: AccountId) -> U64 {
+    U64(set_len(&self.products_by_sellers, &seller))
   }
   // get list buyers of a product
-  pub fn get_buyer_addresses(&self, product_id: String) -> Option<Vec<AccountId>> {
-    self.buyer_addresses.get(&product_id.clone())
+  pub fn get_buyer_addresses(&self, product_id: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<AccountId> {
+    self.buyer_addresses.get(&product_id).map_or(vec![], |buyers| paginate(buyers.iter(), from_index, limit).collect())
+  }
+
+  pub fn get_buyers_count(&self, product_id: String) -> U64 {
+    U64(set_len(&self.buyer_addresses, &product_id))
   }
-  // get list coupons of a seller has created 
-  pub fn get_seller_coupons(&self, seller: AccountId) -> Option<Vec<CouponKey>> {
-    self.coupons_by_seller.get(&seller)
+  // get list coupons of a seller has created
+  pub fn get_seller_coupons(&self, seller: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<CouponKey> {
+    self.coupons_by_seller.get(&seller).map_or(vec![], |coupon_keys| paginate(coupon_keys.iter(), from_index, limit).collect())
+  }
+
+  pub fn get_seller_coupons_count(&self, seller: AccountId) -> U64 {
+    U64(set_len(&self.coupons_by_seller, &seller))
+  }
+  // get coupons of a seller with full details, paginated
+  pub fn get_seller_coupon_details(&self, seller: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<CouponJson> {
+    let coupon_keys = match self.coupons_by_seller.get(&seller) {
+      Some(coupon_keys) => coupon_keys,
+      None => return vec![],
+    };
+
+    paginate(coupon_keys.iter(), from_index, limit)
+      .filter_map(|key| self.coupons.get(&key).map(Coupon::from))
+      .map(CouponJson::from)
+      .collect()
+  }
+  // get redemption history of a coupon code, oldest first. It is kept after the coupon is deleted
+  pub fn get_coupon_redemptions(&self, product_id: String, code: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<CouponRedemption> {
+    self.coupon_redemptions.get(&(product_id, code))
+      .map_or(vec![], |redemptions| paginate(redemptions.iter(), from_index, limit).collect())
+  }
+  // get redemption count, total discount given and revenue of a coupon
+  pub fn get_coupon_stats(&self, product_id: String, code: String) -> CouponStatsJson {
+    let key = (product_id, code);
+    let stats = self.coupon_stats.get(&key).unwrap_or_default();
+    CouponStatsJson {
+      product_id: key.0,
+      code: key.1,
+      redemptions: U64(stats.redemptions),
+      total_discount: U128(stats.total_discount),
+      revenue: U128(stats.revenue),
+    }
+  }
+  // get stats of every coupon a seller has created, paginated
+  pub fn get_seller_coupon_stats(&self, seller: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<CouponStatsJson> {
+    let coupon_keys = match self.coupons_by_seller.get(&seller) {
+      Some(coupon_keys) => coupon_keys,
+      None => return vec![],
+    };
+
+    paginate(coupon_keys.iter(), from_index, limit)
+      .map(|key| self.get_coupon_stats(key.product_id, key.code))
+      .collect()
+  }
+  // visible reviews of a product. Without `sort_by` they come in storage order, which is not
+  // stable: deleting a review moves the last stored one into its place
+  pub fn get_reviews(&self, product_id: String, from_index: Option<U128>, limit: Option<u64>, sort_by: Option<ReviewSort>) -> Vec<Review> {
+    self.list_reviews(&product_id, |_| true, from_index, limit, sort_by)
+  }
+
+  // reviews written by buyers of the product
+  pub fn get_verified_reviews(&self, product_id: String, from_index: Option<U128>, limit: Option<u64>, sort_by: Option<ReviewSort>) -> Vec<Review> {
+    self.list_reviews(&product_id, |review| review.verified, from_index, limit, sort_by)
   }
-  // get review list of a product
-  pub fn get_reviews(&self, product_id: String) -> Option<Vec<Review>> {
-    self.reviews.get(&product_id)
+
+  // hidden reviews are left out, like in `get_reviews`
+  pub fn get_reviews_count(&self, product_id: String) -> U64 {
+    let hidden = self.hidden_review_counts.get(&product_id).unwrap_or(0);
+    U64(self.reviews.get(&product_id).map_or(0, |reviews| reviews.len()) - hidden)
   }
   // get all review from a user
-  pub fn get_my_reviews(&self, reviewer: AccountId) -> Option<Vec<Review>> {
-    self.my_reviews.get(&reviewer)
+  pub fn get_my_reviews(&self, reviewer: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Review> {
+    let product_ids = match self.my_reviews.get(&reviewer) {
+      Some(product_ids) => product_ids,
+      None => return vec![],
+    };
+
+    paginate(product_ids.iter(), from_index, limit)
+      .filter_map(|product_id| self.reviews.get(&product_id)?.get(&reviewer).map(Review::from))
+      .collect()
+  }
+
+  pub fn get_my_reviews_count(&self, reviewer: AccountId) -> U64 {
+    U64(set_len(&self.my_reviews, &reviewer))
   }
 
   pub fn get_coupon_details(&self, product_id: String, code: String, seller: AccountId) -> Option<Coupon> {
     self.coupons.get(&CouponKey {
-      product_id: product_id,
-      code: code,
-      seller: seller,
-    })
+      product_id,
+      code,
+      seller,
+    }).map(Coupon::from)
   }
 
   pub fn get_product_json(&self, product_id: String) -> ProductJson {
 
-    let product_data : Option<Product> = 
-        if let Some(product_data) = self.products.get(&product_id) {
+    let product_data : Option<Product> =
+        if let Some(product_data) = self.products.get(&product_id).map(Product::from) {
             Some( Product {
               id: product_data.id,
               name: product_data.name,
               price: product_data.price,
-              description: product_data.description, 
-              img: product_data.img, 
+              description: product_data.description,
+              img: product_data.img,
               is_active: product_data.is_active,
               seller: product_data.seller,
+              min_price: product_data.min_price,
+              is_archived: product_data.is_archived,
+              category: product_data.category,
+              tags: product_data.tags,
             })
         } else {
             None
         };
-    let product_data = product_data.expect("Product is not exist");
+    let product_data = product_data.unwrap_or_else(|| PaydiiError::ProductNotFound(product_id.clone()).panic());
 
+    let rating = self.get_product_rating(product_id);
     ProductJson {
           id: product_data.id,
           name: product_data.name,
-          description: product_data.description, 
-          img: product_data.img, 
+          description: product_data.description,
+          img: product_data.img,
           is_active: product_data.is_active,
-          seller: product_data.seller
+          seller: product_data.seller,
+          is_archived: product_data.is_archived,
+          category: product_data.category,
+          tags: product_data.tags,
+          rating,
 
           // price: product_data.price.into(),
-          
+
+    }
+
+  }
+}
+
+fn hash_code(code: &str) -> String {
+  env::sha256(code.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn is_code_hash(code: &str) -> bool {
+  code.len() == 64 && code.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+fn assert_discount_fits_price(product: &Product, discount_amount: u128) {
+  require(discount_amount <= product.max_discount(), PaydiiError::DiscountTooHigh {
+    discount: discount_amount,
+    max_discount: product.max_discount(),
+    product_id: product.id.clone(),
+  });
+}
+
+impl Contract {
+  pub(crate) fn has_purchased(&self, buyer: &AccountId, product_id: &str) -> bool {
+    self.tracking.get(&TrackingKey {
+      product_id: product_id.to_string(),
+      reviewer: buyer.clone(),
+      tracking_type: ETrackingType::BuyerProduct as u8
+    }).unwrap_or(false)
+  }
+
+  // the buyer loses access, the purchase stays in their history
+  pub(crate) fn revoke_purchase(&mut self, buyer: &AccountId, product_id: &String) {
+    self.tracking.remove(&TrackingKey {
+      product_id: product_id.clone(),
+      reviewer: buyer.clone(),
+      tracking_type: ETrackingType::BuyerProduct as u8
+    });
+    set_remove(&mut self.buyer_addresses, product_id, buyer);
+  }
+
+  fn list_reviews<F: Fn(&Review) -> bool>(&self, product_id: &String, filter: F, from_index: Option<U128>, limit: Option<u64>, sort_by: Option<ReviewSort>) -> Vec<Review> {
+    let reviews = match self.reviews.get(product_id) {
+      Some(reviews) => reviews,
+      None => return vec![],
+    };
+    let visible = |review: &Review| review.hidden.is_none() && filter(review);
+
+    match sort_by {
+      None => paginate(reviews.values().map(Review::from).filter(visible), from_index, limit).collect(),
+      // the sorted index is walked in order, so only the requested page is read
+      Some(sort_by) => {
+        let ranks = match self.sorted_reviews.get(&(product_id.clone(), sort_by)) {
+          Some(ranks) => ranks,
+          None => return vec![],
+        };
+        let sorted = ranks.iter().filter_map(|((_, _, reviewer), _)| reviews.get(&reviewer)).map(Review::from).filter(visible);
+        paginate(sorted, from_index, limit).collect()
+      }
+    }
+  }
+
+  // moves the review in the sorted views, `None` for a review that was just added or removed
+  pub(crate) fn reindex_review(&mut self, product_id: &str, old: Option<&Review>, new: Option<&Review>) {
+    for sort_by in ReviewSort::ALL {
+      let old_rank = old.map(|review| sort_by.rank(review));
+      let new_rank = new.map(|review| sort_by.rank(review));
+      if old_rank == new_rank {
+        continue;
+      }
+      let key = (product_id.to_string(), sort_by);
+      if let Some(rank) = old_rank {
+        tree_remove(&mut self.sorted_reviews, &key, &rank);
+      }
+      if let Some(rank) = new_rank {
+        tree_insert(&mut self.sorted_reviews, StorageKey::SortedReviewsInner, &key, &rank, &());
+      }
+    }
+  }
+
+  fn clear_review_votes(&mut self, product_id: &str, reviewer: &AccountId) {
+    if let Some(mut votes) = self.review_votes.remove(&(product_id.to_string(), reviewer.clone())) {
+      votes.clear();
+    }
+  }
+
+  pub(crate) fn expect_review(&self, product_id: &String, reviewer: &AccountId) -> Review {
+    self.reviews.get(product_id).and_then(|reviews| reviews.get(reviewer)).map(Review::from)
+      .unwrap_or_else(|| PaydiiError::ReviewNotFound(reviewer.clone()).panic())
+  }
+
+  pub(crate) fn expect_product(&self, product_id: &String) -> Product {
+    self.products.get(product_id).map(Product::from)
+      .unwrap_or_else(|| PaydiiError::ProductNotFound(product_id.clone()).panic())
+  }
+
+  fn remove_product_from_listings(&mut self, product: &Product) {
+    self.product_list.remove(&product.id);
+    self.remove_from_catalog_indexes(product);
+    set_remove(&mut self.products_by_sellers, &product.seller, &product.id);
+  }
+
+  fn assert_coupons_can_transfer(&self, product_id: &String) {
+    require(set_len(&self.coupons_by_product, product_id) <= MAX_TRANSFER_COUPONS as u64, PaydiiError::TooManyCouponsToTransfer { max: MAX_TRANSFER_COUPONS });
+  }
+
+  fn product_coupon_keys(&self, product_id: &String) -> Vec<CouponKey> {
+    self.coupons_by_product.get(product_id).map_or(vec![], |coupon_keys| coupon_keys.to_vec())
+  }
+
+  // remove coupons of one product, their redemption history and stats are kept.
+  // The index sets and discount counts are written once for the whole batch
+  fn remove_coupons(&mut self, product_id: &String, seller: &AccountId, keys: Vec<CouponKey>) {
+    if keys.is_empty() {
+      return;
+    }
+    let mut seller_coupons = self.coupons_by_seller.get(seller);
+    let mut product_coupons = self.coupons_by_product.get(product_id);
+    let mut removed_discounts: BTreeMap<u128, u64> = BTreeMap::new();
+
+    for key in keys.iter() {
+      if let Some(seller_coupons) = seller_coupons.as_mut() {
+        seller_coupons.remove(key);
+      }
+      if let Some(product_coupons) = product_coupons.as_mut() {
+        product_coupons.remove(key);
+      }
+      if let Some(coupon) = self.coupons.remove(key).map(Coupon::from) {
+        *removed_discounts.entry(coupon.discount_amount).or_insert(0) += 1;
+      }
+    }
+
+    match seller_coupons {
+      Some(seller_coupons) if !seller_coupons.is_empty() => { self.coupons_by_seller.insert(seller, &seller_coupons); }
+      _ => { self.coupons_by_seller.remove(seller); }
+    }
+    match product_coupons {
+      Some(product_coupons) if !product_coupons.is_empty() => { self.coupons_by_product.insert(product_id, &product_coupons); }
+      _ => { self.coupons_by_product.remove(product_id); }
+    }
+    for (discount, count) in removed_discounts {
+      self.remove_coupon_discount(product_id, discount, count);
+    }
+    emit_event("coupons_deleted", CouponsDeletedEvent {
+      product_id: product_id.clone(),
+      seller: seller.clone(),
+      codes: keys.into_iter().map(|key| key.code).collect(),
+    });
+  }
+
+  // checks that the biggest coupon discount of the product still gives a valid price
+  fn assert_coupons_fit_price(&self, product: &Product) {
+    if let Some(discount) = self.coupon_discounts.get(&product.id).and_then(|discounts| discounts.max()) {
+      require(discount <= product.max_discount(), PaydiiError::CouponBelowMinPrice {
+        discount,
+        product_id: product.id.clone(),
+        min_price: product.min_price,
+      });
+    }
+  }
+
+  // counts `count` more coupons of the product giving `discount`, so the biggest one is a lookup
+  pub(crate) fn add_coupon_discount(&mut self, product_id: &String, discount: u128, count: u64) {
+    let current = self.coupon_discounts.get(product_id).and_then(|discounts| discounts.get(&discount)).unwrap_or(0);
+    tree_insert(&mut self.coupon_discounts, StorageKey::CouponDiscountsInner, product_id, &discount, &(current + count));
+  }
+
+  fn remove_coupon_discount(&mut self, product_id: &String, discount: u128, count: u64) {
+    match self.coupon_discounts.get(product_id).and_then(|discounts| discounts.get(&discount)) {
+      Some(current) if current > count => {
+        tree_insert(&mut self.coupon_discounts, StorageKey::CouponDiscountsInner, product_id, &discount, &(current - count));
+      }
+      Some(_) => {
+        tree_remove(&mut self.coupon_discounts, product_id, &discount);
+      }
+      None => {}
+    }
+  }
+
+  // takes the price out of the deposit and refunds the rest to the buyer
+  fn pay_seller(&self, seller: AccountId, buyer: AccountId, price: u128, deposit: u128) {
+    require(deposit >= price, PaydiiError::InsufficientDeposit { required: price, attached: deposit });
+
+    Promise::new(seller).transfer(price);
+    if deposit > price {
+      Promise::new(buyer).transfer(deposit - price);
+    }
+  }
+
+  // checks that `buyer` can buy the product, with the coupon if one is given
+  pub(crate) fn validate_purchase(&self, buyer: &AccountId, product_id: &str, coupon_code: Option<String>) -> Result<(Product, Option<Coupon>), PaydiiError> {
+    if self.is_paused(PausableFeature::Purchases) {
+      return Err(PaydiiError::Paused(PausableFeature::Purchases));
+    }
+    let product = self.products.get(&product_id.to_string()).map(Product::from)
+      .ok_or_else(|| PaydiiError::ProductNotFound(product_id.to_string()))?;
+
+    if &product.seller == buyer {
+      return Err(PaydiiError::OwnProduct);
+    }
+    if product.is_archived {
+      return Err(PaydiiError::ProductArchived);
+    }
+    if !product.is_active {
+      return Err(PaydiiError::ProductInactive);
+    }
+
+    if self.has_purchased(buyer, &product.id) {
+      return Err(PaydiiError::AlreadyPurchased);
     }
-    
+
+    // check the coupon first, it is only used if it ends up in the final price
+    let coupon = match coupon_code {
+      Some(code) => {
+        if self.is_paused(PausableFeature::CouponRedemption) {
+          return Err(PaydiiError::Paused(PausableFeature::CouponRedemption));
+        }
+        let hashed_coupon_key = CouponKey {
+          product_id: product.id.clone(),
+          code: hash_code(&code),
+          seller: product.seller.clone() };
+        let current_coupon_key = CouponKey {
+          product_id: product.id.clone(),
+          code,
+          seller: product.seller.clone() };
+        // get coupon details, coupons created from hashes only match the hash of the typed code,
+        // never the hash itself, which anyone can read from the chain
+        let coupon = self.coupons.get(&current_coupon_key).map(Coupon::from).filter(|coupon| !coupon.is_hashed)
+          .or_else(|| self.coupons.get(&hashed_coupon_key).map(Coupon::from).filter(|coupon| coupon.is_hashed))
+          .ok_or(PaydiiError::CouponNotFound)?;
+        if !coupon.is_active {
+          return Err(PaydiiError::CouponInactive);
+        }
+        if coupon.allowed_uses == 0 {
+          return Err(PaydiiError::CouponExhausted);
+        }
+        if let Some(nft_contract_id) = &coupon.nft_contract_id {
+          if !self.nft_contract_ids.contains(nft_contract_id) {
+            return Err(PaydiiError::NftContractNotAllowed(nft_contract_id.clone()));
+          }
+        }
+        Some(coupon)
+      }
+      None => None
+    };
+
+    Ok((product, coupon))
+  }
+
+  // what the buyer pays right now with the coupon and any running promotion
+  pub(crate) fn purchase_price(&self, product: &Product, coupon: Option<&Coupon>) -> u128 {
+    self.price_breakdown(product, coupon.map(|coupon| coupon.discount_amount)).price
   }
-  
 
- 
-}
\ No newline at end of file
+  // takes the payment and records the purchase, the product and coupon must be validated already
+  pub(crate) fn complete_purchase(&mut self, buyer: AccountId, product: Product, coupon: Option<Coupon>, deposit: u128) -> bool {
+    // apply the coupon and any running promotion, never under the seller's minimum price
+    let breakdown = self.price_breakdown(&product, coupon.as_ref().map(|coupon| coupon.discount_amount));
+    let purchased_price: u128 = breakdown.price;
+
+    // Buyer sends Near to seller
+    self.pay_seller(product.seller.clone(), buyer.clone(), purchased_price, deposit);
+    self.record_sale(&product.seller, purchased_price);
+
+    if let Some(scope) = &breakdown.promotion {
+      self.use_promotion(scope);
+    }
+
+    // the part of the discount that came from the coupon, 0 if the coupon wasn't used
+    let coupon_discount = breakdown.coupon_discount;
+    let coupon_code = coupon.as_ref().filter(|_| breakdown.coupon_discount > 0).map(|coupon| coupon.code.clone());
+    let receipt = self.add_receipt(Receipt {
+      id: U64(0),
+      buyer: buyer.clone(),
+      // the deposit always comes from the buyer, also when an NFT coupon check ran in between
+      payer: buyer.clone(),
+      seller: product.seller.clone(),
+      product_id: product.id.clone(),
+      price: U128(product.price),
+      paid_price: U128(purchased_price),
+      coupon: coupon_code.clone().map(|code| ReceiptCoupon { code, discount: U128(coupon_discount) }),
+      promotion: breakdown.promotion.clone(),
+      payment_token: None,
+      timestamp: U64(env::block_timestamp()),
+      block_height: U64(env::block_height()),
+      refunded_at: None,
+    });
+    emit_event("purchase", PurchaseEvent {
+      receipt_id: receipt.id,
+      buyer: buyer.clone(),
+      seller: product.seller.clone(),
+      product_id: product.id.clone(),
+      price: U128(product.price),
+      paid_price: U128(purchased_price),
+      coupon_code,
+      coupon_discount: U128(coupon_discount),
+      promotion: breakdown.promotion.clone(),
+    });
+
+    if let Some(coupon) = coupon.filter(|_| breakdown.coupon_discount > 0) {
+        let updated_coupon = Coupon {
+          allowed_uses: coupon.allowed_uses - 1,
+          ..coupon };
+
+        // update coupon
+        self.coupons.insert(&updated_coupon.key(), &updated_coupon.clone().into());
+
+        self.record_coupon_redemption(&updated_coupon.key(), CouponRedemption {
+          buyer: buyer.clone(),
+          product_id: product.id.clone(),
+          discount_amount: U128(coupon_discount),
+          paid_price: U128(purchased_price),
+          timestamp: U64(env::block_timestamp()),
+        });
+        emit_event("coupon_redeemed", CouponRedeemedEvent {
+          product_id: product.id.clone(),
+          code: updated_coupon.code,
+          seller: updated_coupon.seller,
+          buyer: buyer.clone(),
+          discount_amount: U128(coupon_discount),
+          paid_price: U128(purchased_price),
+          remaining_uses: U128(updated_coupon.allowed_uses),
+        });
+    }
+
+    let new_purchase_info = PurchaseInfo {
+      product_id: product.id,
+      origin_price: product.price,
+      profit_price: purchased_price,
+      receipt_id: Some(receipt.id) };
+
+    let product_id = new_purchase_info.product_id.clone();
+
+    // update list of purchased products
+    vector_push(&mut self.buyers, StorageKey::BuyersInner, &buyer, &new_purchase_info.into());
+    set_insert(&mut self.buyer_addresses, StorageKey::BuyerAddressesInner, &product_id, &buyer);
+
+    self.tracking.insert(&TrackingKey {
+      product_id,
+      reviewer: buyer,
+      tracking_type: ETrackingType::BuyerProduct as u8 },&true);
+
+    true
+  }
+
+  fn record_coupon_redemption(&mut self, key: &CouponKey, redemption: CouponRedemption) {
+    let key = &key.history_key();
+    let mut stats = self.coupon_stats.get(key).unwrap_or_default();
+    stats.redemptions += 1;
+    stats.total_discount += redemption.discount_amount.0;
+    stats.revenue += redemption.paid_price.0;
+    self.coupon_stats.insert(key, &stats);
+
+    vector_push(&mut self.coupon_redemptions, StorageKey::CouponRedemptionsInner, key, &redemption);
+  }
+}
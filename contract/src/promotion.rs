@@ -0,0 +1,180 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::paydii::Product;
+use crate::errors::{require, PaydiiError};
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use near_sdk::json_types::{U128, U64};
+
+// a promotion runs on every product of a seller or on a single product
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PromotionScope {
+  Seller(AccountId),
+  Product(String),
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PromotionDiscount {
+  Percent(u8), // 1 - 100 % of the product price
+  Amount(U128), // fixed amount in yoctoNEAR
+}
+
+// how a promotion combines with a coupon the buyer typed in
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CouponCombination {
+  BestPrice, // only the bigger discount of the two is applied
+  Stack, // both discounts are applied
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Promotion {
+  pub scope: PromotionScope,
+  pub seller: AccountId,
+  pub discount: PromotionDiscount,
+  pub starts_at: U64, // block timestamp in nanoseconds
+  pub ends_at: U64,
+  pub max_uses: Option<U64>, // None => unlimited
+  pub uses: U64,
+  pub combination: CouponCombination,
+}
+
+impl Promotion {
+  pub fn is_running(&self, now: u64) -> bool {
+    self.starts_at.0 <= now
+      && now < self.ends_at.0
+      && self.max_uses.is_none_or(|max_uses| self.uses.0 < max_uses.0)
+  }
+
+  pub fn discount_for(&self, price: u128) -> u128 {
+    match self.discount {
+      // same as price * percent / 100 without overflowing for prices above u128::MAX / 100
+      PromotionDiscount::Percent(percent) => price / 100 * percent as u128 + price % 100 * percent as u128 / 100,
+      PromotionDiscount::Amount(amount) => amount.0.min(price),
+    }
+  }
+}
+
+// final price of a purchase and which discounts were used to get there
+pub struct PriceBreakdown {
+  pub price: u128,
+  pub coupon_discount: u128, // 0 when the coupon didn't lower the price, it is then kept for a later purchase
+  pub promotion: Option<PromotionScope>,
+}
+
+#[near_bindgen]
+impl Contract {
+
+  // start a promotion on every product of the caller
+  pub fn set_seller_promotion(&mut self, discount: PromotionDiscount, starts_at: U64, ends_at: U64, max_uses: Option<U64>, combination: CouponCombination) -> Promotion {
+    let seller = env::predecessor_account_id();
+    self.save_promotion(PromotionScope::Seller(seller.clone()), seller, discount, starts_at, ends_at, max_uses, combination)
+  }
+
+  // start a promotion on one product, it takes priority over the seller's promotion
+  pub fn set_product_promotion(&mut self, product_id: String, discount: PromotionDiscount, starts_at: U64, ends_at: U64, max_uses: Option<U64>, combination: CouponCombination) -> Promotion {
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+
+    if let PromotionDiscount::Amount(amount) = discount {
+      require(amount.0 <= product.price, PaydiiError::PromotionDiscountTooHigh { discount: amount.0, price: product.price });
+    }
+    self.save_promotion(PromotionScope::Product(product_id), product.seller, discount, starts_at, ends_at, max_uses, combination)
+  }
+
+  pub fn remove_seller_promotion(&mut self) -> bool {
+    self.promotions.remove(&PromotionScope::Seller(env::predecessor_account_id())).is_some()
+  }
+
+  pub fn remove_product_promotion(&mut self, product_id: String) -> bool {
+    let product = self.expect_product(&product_id);
+    require(product.seller == env::predecessor_account_id(), PaydiiError::NotOwner);
+    self.promotions.remove(&PromotionScope::Product(product_id)).is_some()
+  }
+
+  pub fn get_seller_promotion(&self, seller: AccountId) -> Option<Promotion> {
+    self.promotions.get(&PromotionScope::Seller(seller))
+  }
+
+  pub fn get_product_promotion(&self, product_id: String) -> Option<Promotion> {
+    self.promotions.get(&PromotionScope::Product(product_id))
+  }
+
+  // the promotion buy_product would apply to this product right now
+  pub fn get_active_promotion(&self, product_id: String) -> Option<Promotion> {
+    let product = self.products.get(&product_id).map(Product::from)?;
+    self.active_promotion(&product)
+  }
+
+  // the price a buyer without a coupon would pay right now
+  pub fn get_promotion_price(&self, product_id: String) -> U128 {
+    let product = self.expect_product(&product_id);
+    U128(self.price_breakdown(&product, None).price)
+  }
+}
+
+impl Contract {
+  #[allow(clippy::too_many_arguments)]
+  fn save_promotion(&mut self, scope: PromotionScope, seller: AccountId, discount: PromotionDiscount, starts_at: U64, ends_at: U64, max_uses: Option<U64>, combination: CouponCombination) -> Promotion {
+    require(starts_at.0 < ends_at.0, PaydiiError::InvalidPromotionPeriod);
+    if let PromotionDiscount::Percent(percent) = discount {
+      require((1..=100).contains(&percent), PaydiiError::InvalidPromotionPercent);
+    }
+
+    let promotion = Promotion {
+      scope: scope.clone(),
+      seller,
+      discount,
+      starts_at,
+      ends_at,
+      max_uses,
+      uses: U64(0),
+      combination,
+    };
+    self.promotions.insert(&scope, &promotion);
+    promotion
+  }
+
+  pub(crate) fn active_promotion(&self, product: &Product) -> Option<Promotion> {
+    let now = env::block_timestamp();
+    [PromotionScope::Product(product.id.clone()), PromotionScope::Seller(product.seller.clone())]
+      .iter()
+      .filter_map(|scope| self.promotions.get(scope))
+      .find(|promotion| promotion.is_running(now))
+  }
+
+  // works out the price of a product given an optional coupon discount
+  pub(crate) fn price_breakdown(&self, product: &Product, coupon_discount: Option<u128>) -> PriceBreakdown {
+    let promotion = self.active_promotion(product);
+    let promotion_discount = promotion.as_ref().map_or(0, |promotion| promotion.discount_for(product.price));
+    let coupon_discount = coupon_discount.unwrap_or(0);
+
+    let (coupon_discount, promotion_discount) = match promotion.as_ref().map(|promotion| promotion.combination) {
+      Some(CouponCombination::Stack) => (coupon_discount, promotion_discount),
+      // best price wins, the coupon is kept for a later purchase if the promotion is better
+      _ if coupon_discount >= promotion_discount => (coupon_discount, 0),
+      _ => (0, promotion_discount),
+    };
+
+    // the coupon only counts for what it takes off on top of the promotion, `min_price` can leave nothing for it
+    let promotion_price = product.discounted_price(promotion_discount);
+    let price = product.discounted_price(coupon_discount.saturating_add(promotion_discount));
+    PriceBreakdown {
+      price,
+      coupon_discount: promotion_price - price,
+      promotion: promotion.filter(|_| promotion_discount > 0).map(|promotion| promotion.scope),
+    }
+  }
+
+  pub(crate) fn use_promotion(&mut self, scope: &PromotionScope) {
+    if let Some(mut promotion) = self.promotions.get(scope) {
+      promotion.uses = U64(promotion.uses.0 + 1);
+      self.promotions.insert(scope, &promotion);
+    }
+  }
+}
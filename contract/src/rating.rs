@@ -0,0 +1,113 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::paydii::Product;
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{near_bindgen, AccountId};
+use near_sdk::json_types::U64;
+
+pub const MIN_STAR: u64 = 1;
+pub const MAX_STAR: u64 = 5;
+
+// running totals of a product's reviews, updated whenever a review is added, edited or removed
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct ProductRating {
+  count: u64,
+  sum: u64,
+  histogram: [u64; MAX_STAR as usize], // histogram[0] counts 1 star reviews
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductRatingJson {
+  pub count: U64,
+  pub sum: U64, // average = sum / count
+  pub histogram: Vec<U64>,
+}
+
+impl From<ProductRating> for ProductRatingJson {
+  fn from(rating: ProductRating) -> Self {
+    ProductRatingJson {
+      count: U64(rating.count),
+      sum: U64(rating.sum),
+      histogram: rating.histogram.iter().map(|count| U64(*count)).collect(),
+    }
+  }
+}
+
+// a product along with its rating, returned by the product views
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductView {
+  #[serde(flatten)]
+  pub product: Product,
+  pub rating: ProductRatingJson,
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn get_product_rating(&self, product_id: String) -> ProductRatingJson {
+    self.product_ratings.get(&product_id).unwrap_or_default().into()
+  }
+}
+
+impl Contract {
+  pub(crate) fn product_view(&self, product: Product) -> ProductView {
+    let rating = self.get_product_rating(product.id.clone());
+    ProductView { product, rating }
+  }
+
+  pub(crate) fn add_to_rating(&mut self, product_id: &String, star: u64) {
+    // the first release took any star, those reviews are never counted
+    if !(MIN_STAR..=MAX_STAR).contains(&star) {
+      return;
+    }
+    let mut rating = self.product_ratings.get(product_id).unwrap_or_default();
+    rating.count += 1;
+    rating.sum += star;
+    rating.histogram[(star - MIN_STAR) as usize] += 1;
+    self.product_ratings.insert(product_id, &rating);
+    if let Some(seller) = self.product_seller(product_id) {
+      self.record_rating(&seller, 1, star, true);
+    }
+  }
+
+  pub(crate) fn remove_from_rating(&mut self, product_id: &String, star: u64) {
+    // the first release took any star, those reviews were never counted
+    if !(MIN_STAR..=MAX_STAR).contains(&star) {
+      return;
+    }
+    let mut rating = self.product_ratings.get(product_id).unwrap_or_default();
+    rating.count -= 1;
+    rating.sum -= star;
+    rating.histogram[(star - MIN_STAR) as usize] -= 1;
+    if rating.count == 0 {
+      self.product_ratings.remove(product_id);
+    } else {
+      self.product_ratings.insert(product_id, &rating);
+    }
+    if let Some(seller) = self.product_seller(product_id) {
+      self.record_rating(&seller, 1, star, false);
+    }
+  }
+
+  // drops the rating of a deleted product, along with its share of the seller's reputation
+  pub(crate) fn remove_product_rating(&mut self, product: &Product) {
+    if let Some(rating) = self.product_ratings.remove(&product.id) {
+      self.record_rating(&product.seller, rating.count, rating.sum, false);
+    }
+  }
+
+  // the rating of a transferred product counts for its new seller from now on
+  pub(crate) fn move_product_rating(&mut self, product_id: &String, old_seller: &AccountId, new_seller: &AccountId) {
+    if let Some(rating) = self.product_ratings.get(product_id) {
+      self.record_rating(old_seller, rating.count, rating.sum, false);
+      self.record_rating(new_seller, rating.count, rating.sum, true);
+    }
+  }
+
+  fn product_seller(&self, product_id: &String) -> Option<AccountId> {
+    self.products.get(product_id).map(|product| Product::from(product).seller)
+  }
+}
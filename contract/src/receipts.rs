@@ -0,0 +1,98 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::promotion::PromotionScope;
+use crate::pagination::paginate;
+use crate::index::{vector_len, vector_push};
+use crate::StorageKey;
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::{near_bindgen, AccountId};
+use near_sdk::json_types::{U128, U64};
+
+// one per completed purchase, ids count up from 0 in the order purchases complete
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Receipt {
+  pub id: U64,
+  pub buyer: AccountId,
+  pub payer: AccountId, // the account whose deposit paid for the purchase
+  pub seller: AccountId, // the seller at the time of the purchase
+  pub product_id: String,
+  pub price: U128, // the product's price before discounts
+  pub paid_price: U128,
+  pub coupon: Option<ReceiptCoupon>,
+  pub promotion: Option<PromotionScope>,
+  pub payment_token: Option<AccountId>, // None when paid in NEAR
+  pub timestamp: U64,
+  pub block_height: U64,
+  pub refunded_at: Option<U64>, // the buyer lost access to the product then
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReceiptCoupon {
+  pub code: String,
+  pub discount: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn get_receipt(&self, id: U64) -> Option<Receipt> {
+    self.receipts.get(id.0)
+  }
+
+  // receipts of one buyer, oldest first. `from_time` and `to_time` are inclusive block timestamps
+  pub fn get_receipts_of_buyer(&self, buyer: AccountId, from_time: Option<U64>, to_time: Option<U64>, from_index: Option<U128>, limit: Option<u64>) -> Vec<Receipt> {
+    self.receipts_in_range(self.receipts_by_buyer.get(&buyer), from_time, to_time, from_index, limit)
+  }
+
+  pub fn get_receipts_count_of_buyer(&self, buyer: AccountId) -> U64 {
+    U64(vector_len(&self.receipts_by_buyer, &buyer))
+  }
+
+  pub fn get_receipts_of_seller(&self, seller: AccountId, from_time: Option<U64>, to_time: Option<U64>, from_index: Option<U128>, limit: Option<u64>) -> Vec<Receipt> {
+    self.receipts_in_range(self.receipts_by_seller.get(&seller), from_time, to_time, from_index, limit)
+  }
+
+  pub fn get_receipts_count_of_seller(&self, seller: AccountId) -> U64 {
+    U64(vector_len(&self.receipts_by_seller, &seller))
+  }
+}
+
+impl Contract {
+  // stores the receipt under the next id, the id already set on `receipt` is replaced
+  pub(crate) fn add_receipt(&mut self, receipt: Receipt) -> Receipt {
+    let id = self.receipts.len();
+    let receipt = Receipt { id: U64(id), ..receipt };
+    self.receipts.push(&receipt);
+    vector_push(&mut self.receipts_by_buyer, StorageKey::ReceiptsByBuyerInner, &receipt.buyer, &id);
+    vector_push(&mut self.receipts_by_seller, StorageKey::ReceiptsBySellerInner, &receipt.seller, &id);
+    receipt
+  }
+
+  fn receipts_in_range(&self, ids: Option<Vector<u64>>, from_time: Option<U64>, to_time: Option<U64>, from_index: Option<U128>, limit: Option<u64>) -> Vec<Receipt> {
+    let ids = match ids {
+      Some(ids) => ids,
+      None => return vec![],
+    };
+    let from_time = from_time.map_or(0, u64::from);
+    let to_time = to_time.map_or(u64::MAX, u64::from);
+    let receipt_at = |index: u64| self.receipts.get(ids.get(index).unwrap()).unwrap();
+
+    // ids are appended in block order, so the first receipt in range is found by binary search
+    let (mut low, mut high) = (0, ids.len());
+    while low < high {
+      let middle = low + (high - low) / 2;
+      if receipt_at(middle).timestamp.0 < from_time {
+        low = middle + 1;
+      } else {
+        high = middle;
+      }
+    }
+
+    let in_range = (low..ids.len()).map(receipt_at).take_while(|receipt| receipt.timestamp.0 <= to_time);
+    paginate(in_range, from_index, limit).collect()
+  }
+}
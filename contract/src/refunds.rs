@@ -0,0 +1,139 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::receipts::Receipt;
+use crate::access::Role;
+use crate::errors::{require, PaydiiError};
+use crate::events::{emit_event, PurchaseRefundedEvent};
+use crate::pagination::paginate;
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId, Promise};
+use near_sdk::json_types::{U128, U64};
+
+pub const MAX_DISPUTE_REASON_LENGTH: usize = 280;
+
+// one per receipt, opened by the buyer and settled by an arbitrator
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Dispute {
+  pub receipt_id: U64,
+  pub buyer: AccountId,
+  pub seller: AccountId,
+  pub product_id: String,
+  pub reason: String,
+  pub opened_at: U64,
+  pub resolution: Option<DisputeResolution>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DisputeResolution {
+  pub arbitrator: AccountId,
+  pub buyer_wins: bool, // counts as a lost dispute in the seller's reputation
+  pub resolved_at: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+
+  // the seller who was paid sends the paid price back, attached to this call, and the buyer loses access.
+  // Refunds keep working while purchases are paused
+  #[payable]
+  pub fn refund_purchase(&mut self, receipt_id: U64) -> Receipt {
+    let mut receipt = self.expect_receipt(receipt_id.0);
+    let seller = env::predecessor_account_id();
+    require(receipt.seller == seller, PaydiiError::NotReceiptSeller);
+    require(receipt.refunded_at.is_none(), PaydiiError::AlreadyRefunded);
+
+    let amount = receipt.paid_price.0;
+    let deposit = env::attached_deposit();
+    require(deposit >= amount, PaydiiError::InsufficientDeposit { required: amount, attached: deposit });
+    if amount > 0 {
+      Promise::new(receipt.payer.clone()).transfer(amount);
+    }
+    if deposit > amount {
+      Promise::new(seller.clone()).transfer(deposit - amount);
+    }
+
+    receipt.refunded_at = Some(U64(env::block_timestamp()));
+    self.receipts.replace(receipt_id.0, &receipt);
+    self.revoke_purchase(&receipt.buyer, &receipt.product_id);
+    self.record_refund(&seller, amount);
+
+    emit_event("purchase_refunded", PurchaseRefundedEvent {
+      receipt_id,
+      buyer: receipt.buyer.clone(),
+      seller,
+      product_id: receipt.product_id.clone(),
+      amount: U128(amount),
+    });
+    receipt
+  }
+
+  // the buyer disputes a purchase that wasn't refunded, once per receipt
+  pub fn open_dispute(&mut self, receipt_id: U64, reason: String) -> Dispute {
+    require(!reason.is_empty() && reason.len() <= MAX_DISPUTE_REASON_LENGTH, PaydiiError::InvalidDisputeReason { max_length: MAX_DISPUTE_REASON_LENGTH });
+    let receipt = self.expect_receipt(receipt_id.0);
+    require(receipt.buyer == env::predecessor_account_id(), PaydiiError::NotReceiptBuyer);
+    require(receipt.refunded_at.is_none(), PaydiiError::AlreadyRefunded);
+    require(self.disputes.get(&receipt_id.0).is_none(), PaydiiError::DisputeExists);
+
+    let dispute = Dispute {
+      receipt_id,
+      buyer: receipt.buyer,
+      seller: receipt.seller,
+      product_id: receipt.product_id,
+      reason,
+      opened_at: U64(env::block_timestamp()),
+      resolution: None,
+    };
+    self.disputes.insert(&receipt_id.0, &dispute);
+    self.open_disputes.insert(&receipt_id.0);
+
+    emit_event("dispute_opened", dispute.clone());
+    dispute
+  }
+
+  // only the owner or an arbitrator
+  pub fn resolve_dispute(&mut self, receipt_id: U64, buyer_wins: bool) -> Dispute {
+    self.assert_role(Role::Arbitrator);
+    let mut dispute = self.disputes.get(&receipt_id.0).unwrap_or_else(|| PaydiiError::DisputeNotFound.panic());
+    require(dispute.resolution.is_none(), PaydiiError::DisputeResolved);
+
+    dispute.resolution = Some(DisputeResolution {
+      arbitrator: env::predecessor_account_id(),
+      buyer_wins,
+      resolved_at: U64(env::block_timestamp()),
+    });
+    self.disputes.insert(&receipt_id.0, &dispute);
+    self.open_disputes.remove(&receipt_id.0);
+    if buyer_wins {
+      self.record_dispute_lost(&dispute.seller);
+    }
+
+    emit_event("dispute_resolved", dispute.clone());
+    dispute
+  }
+
+  pub fn get_dispute(&self, receipt_id: U64) -> Option<Dispute> {
+    self.disputes.get(&receipt_id.0)
+  }
+
+  // disputes waiting for an arbitrator
+  pub fn get_open_disputes(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Dispute> {
+    paginate(self.open_disputes.iter(), from_index, limit)
+      .filter_map(|receipt_id| self.disputes.get(&receipt_id))
+      .collect()
+  }
+
+  pub fn get_open_disputes_count(&self) -> U64 {
+    U64(self.open_disputes.len())
+  }
+}
+
+impl Contract {
+  fn expect_receipt(&self, receipt_id: u64) -> Receipt {
+    self.receipts.get(receipt_id).unwrap_or_else(|| PaydiiError::ReceiptNotFound(receipt_id).panic())
+  }
+}
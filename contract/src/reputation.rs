@@ -0,0 +1,102 @@
+use crate::Contract;
+use crate::ContractExt;
+
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+use near_sdk::json_types::{U128, U64};
+
+// running totals of one seller, updated on every sale, rating change, refund and lost dispute
+// instead of scanning their products
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct SellerReputation {
+  sales: u64,
+  revenue: Balance, // refunded purchases are taken out again
+  rating_count: u64,
+  rating_sum: u64,
+  refunds: u64,
+  disputes_lost: u64,
+  joined_at: u64, // when the seller listed their first product
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SellerReputationJson {
+  pub seller: AccountId,
+  pub sales: U64,
+  pub revenue: U128,
+  pub rating_count: U64,
+  pub rating_sum: U64, // average over all products = rating_sum / rating_count
+  pub refunds: U64,
+  pub refund_rate_bps: u32, // refunds per 10 000 sales
+  pub disputes_lost: U64,
+  pub joined_at: U64,
+  pub account_age: U64, // nanoseconds since `joined_at`
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn get_seller_reputation(&self, seller: AccountId) -> Option<SellerReputationJson> {
+    self.seller_reputations.get(&seller).map(|reputation| SellerReputationJson {
+      seller,
+      sales: U64(reputation.sales),
+      revenue: U128(reputation.revenue),
+      rating_count: U64(reputation.rating_count),
+      rating_sum: U64(reputation.rating_sum),
+      refunds: U64(reputation.refunds),
+      refund_rate_bps: (reputation.refunds * 10_000).checked_div(reputation.sales).unwrap_or(0) as u32,
+      disputes_lost: U64(reputation.disputes_lost),
+      joined_at: U64(reputation.joined_at),
+      account_age: U64(env::block_timestamp().saturating_sub(reputation.joined_at)),
+    })
+  }
+}
+
+impl Contract {
+  fn update_reputation(&mut self, seller: &AccountId, update: impl FnOnce(&mut SellerReputation)) {
+    let mut reputation = self.seller_reputations.get(seller).unwrap_or_else(|| SellerReputation {
+      joined_at: env::block_timestamp(),
+      ..Default::default()
+    });
+    update(&mut reputation);
+    self.seller_reputations.insert(seller, &reputation);
+  }
+
+  // starts the seller's account age, later products keep the first date
+  pub(crate) fn record_seller(&mut self, seller: &AccountId) {
+    if self.seller_reputations.get(seller).is_none() {
+      self.update_reputation(seller, |_| {});
+    }
+  }
+
+  pub(crate) fn record_sale(&mut self, seller: &AccountId, paid_price: Balance) {
+    self.update_reputation(seller, |reputation| {
+      reputation.sales += 1;
+      reputation.revenue += paid_price;
+    });
+  }
+
+  // `count` stars totalling `sum` were added to (or, with `added` false, taken from) one of the seller's products
+  pub(crate) fn record_rating(&mut self, seller: &AccountId, count: u64, sum: u64, added: bool) {
+    self.update_reputation(seller, |reputation| {
+      if added {
+        reputation.rating_count += count;
+        reputation.rating_sum += sum;
+      } else {
+        reputation.rating_count -= count;
+        reputation.rating_sum -= sum;
+      }
+    });
+  }
+
+  pub(crate) fn record_refund(&mut self, seller: &AccountId, paid_price: Balance) {
+    self.update_reputation(seller, |reputation| {
+      reputation.refunds += 1;
+      reputation.revenue -= paid_price;
+    });
+  }
+
+  pub(crate) fn record_dispute_lost(&mut self, seller: &AccountId) {
+    self.update_reputation(seller, |reputation| reputation.disputes_lost += 1);
+  }
+}
@@ -0,0 +1,94 @@
+use crate::Contract;
+use crate::ContractExt;
+use crate::errors::{require, PaydiiError};
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise, StorageUsage};
+use near_sdk::json_types::U128;
+
+#[near_bindgen]
+impl Contract {
+  // storage staking a seller has paid for and can get back by deleting or archiving products
+  pub fn get_seller_storage_balance(&self, seller: AccountId) -> U128 {
+    U128(self.seller_storage.get(&seller).unwrap_or(0))
+  }
+}
+
+impl Contract {
+  // charges the seller for the bytes of the product written since `initial_storage` and refunds the rest of the deposit
+  pub(crate) fn charge_storage(&mut self, seller: &AccountId, product_id: &String, initial_storage: StorageUsage) {
+    let used = env::storage_usage().saturating_sub(initial_storage);
+    let cost = used as u128 * env::storage_byte_cost();
+    let deposit = env::attached_deposit();
+    require(deposit >= cost, PaydiiError::InsufficientStorageDeposit { required: cost });
+
+    let balance = self.seller_storage.get(seller).unwrap_or(0);
+    self.seller_storage.insert(seller, &(balance + cost));
+    let product_balance = self.product_storage.get(product_id).unwrap_or(0);
+    self.product_storage.insert(product_id, &(product_balance + cost));
+
+    if deposit > cost {
+      Promise::new(env::predecessor_account_id()).transfer(deposit - cost);
+    }
+  }
+
+  // refunds the bytes of the product freed since `initial_storage`, up to what was paid for the product
+  pub(crate) fn refund_storage(&mut self, seller: &AccountId, product_id: &String, initial_storage: StorageUsage) {
+    let freed = initial_storage.saturating_sub(env::storage_usage());
+    let product_balance = self.product_storage.get(product_id).unwrap_or(0);
+    let refund = (freed as u128 * env::storage_byte_cost()).min(product_balance);
+
+    if refund == 0 {
+      return;
+    }
+    update_balance(&mut self.product_storage, product_id, product_balance - refund);
+    let balance = self.seller_storage.get(seller).unwrap_or(0);
+    update_balance(&mut self.seller_storage, seller, balance - refund);
+    Promise::new(seller.clone()).transfer(refund);
+  }
+
+  // charges the seller for a call that grew the product's storage, or refunds what it freed along with the deposit
+  pub(crate) fn settle_storage(&mut self, seller: &AccountId, product_id: &String, initial_storage: StorageUsage) {
+    if env::storage_usage() > initial_storage {
+      self.charge_storage(seller, product_id, initial_storage);
+      return;
+    }
+    self.refund_storage(seller, product_id, initial_storage);
+    let deposit = env::attached_deposit();
+    if deposit > 0 {
+      Promise::new(env::predecessor_account_id()).transfer(deposit);
+    }
+  }
+
+  // drops the storage balance of a deleted product. What was not refunded pays for bytes that stay
+  // behind, like the seller's reputation, so it is no longer the seller's to get back
+  pub(crate) fn clear_product_storage(&mut self, seller: &AccountId, product_id: &String) {
+    let leftover = self.product_storage.remove(product_id).unwrap_or(0);
+    if leftover == 0 {
+      return;
+    }
+    let balance = self.seller_storage.get(seller).unwrap_or(0);
+    update_balance(&mut self.seller_storage, seller, balance - leftover);
+  }
+
+  // the storage paid for a transferred product is the new seller's to get back
+  pub(crate) fn move_product_storage(&mut self, product_id: &String, old_seller: &AccountId, new_seller: &AccountId) {
+    let amount = self.product_storage.get(product_id).unwrap_or(0);
+    if amount == 0 {
+      return;
+    }
+    let old_balance = self.seller_storage.get(old_seller).unwrap_or(0);
+    update_balance(&mut self.seller_storage, old_seller, old_balance - amount);
+    let new_balance = self.seller_storage.get(new_seller).unwrap_or(0);
+    update_balance(&mut self.seller_storage, new_seller, new_balance + amount);
+  }
+}
+
+fn update_balance<K: BorshSerialize + BorshDeserialize>(balances: &mut UnorderedMap<K, Balance>, key: &K, balance: Balance) {
+  if balance == 0 {
+    balances.remove(key);
+  } else {
+    balances.insert(key, &balance);
+  }
+}
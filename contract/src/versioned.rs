@@ -0,0 +1,181 @@
+use crate::paydii::{Product, Coupon, Review, PurchaseInfo};
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::AccountId;
+use near_sdk::json_types::U64;
+
+// Stored entities are wrapped in an enum with one variant per layout. When a layout changes,
+// the current struct is copied here as the next `...Vn` struct, a variant is added, and the
+// `From` impl fills in the new fields. Old entries are upgraded when they are read, so a
+// layout change never needs to rewrite the whole map.
+
+pub const STATE_VERSION: u8 = 2;
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+
+// the first release has no marker in storage, so a missing marker means version 1
+pub(crate) fn read_state_version() -> u8 {
+  near_sdk::env::storage_read(STATE_VERSION_KEY).map_or(1, |bytes| bytes[0])
+}
+
+pub(crate) fn write_state_version() {
+  near_sdk::env::storage_write(STATE_VERSION_KEY, &[STATE_VERSION]);
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ProductV1 {
+  pub id: String,
+  pub name: String,
+  pub price: u128,
+  pub description: String,
+  pub img: String,
+  pub is_active: bool,
+  pub seller: AccountId,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedProduct {
+  V1(ProductV1),
+  V2(Product),
+}
+
+impl From<VersionedProduct> for Product {
+  fn from(product: VersionedProduct) -> Self {
+    match product {
+      VersionedProduct::V1(product) => Product {
+        id: product.id,
+        name: product.name,
+        price: product.price,
+        description: product.description,
+        img: product.img,
+        is_active: product.is_active,
+        seller: product.seller,
+        min_price: 0,
+        is_archived: false,
+        category: None,
+        tags: vec![],
+      },
+      VersionedProduct::V2(product) => product,
+    }
+  }
+}
+
+impl From<Product> for VersionedProduct {
+  fn from(product: Product) -> Self {
+    VersionedProduct::V2(product)
+  }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CouponV1 {
+  pub code: String,
+  pub product_id: String,
+  pub discount_amount: u128,
+  pub allowed_uses: u128,
+  pub seller: AccountId,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedCoupon {
+  V1(CouponV1),
+  V2(Coupon),
+}
+
+impl From<VersionedCoupon> for Coupon {
+  fn from(coupon: VersionedCoupon) -> Self {
+    match coupon {
+      VersionedCoupon::V1(coupon) => Coupon {
+        code: coupon.code,
+        product_id: coupon.product_id,
+        discount_amount: coupon.discount_amount,
+        allowed_uses: coupon.allowed_uses,
+        seller: coupon.seller,
+        is_active: true,
+        nft_contract_id: None,
+        is_hashed: false,
+      },
+      VersionedCoupon::V2(coupon) => coupon,
+    }
+  }
+}
+
+impl From<Coupon> for VersionedCoupon {
+  fn from(coupon: Coupon) -> Self {
+    VersionedCoupon::V2(coupon)
+  }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct ReviewV1 {
+  pub product_id: String,
+  pub reviewer: AccountId,
+  pub content: String,
+  pub star: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedReview {
+  V1(ReviewV1),
+  V2(Review),
+}
+
+impl From<VersionedReview> for Review {
+  fn from(review: VersionedReview) -> Self {
+    match review {
+      VersionedReview::V1(review) => Review {
+        product_id: review.product_id,
+        reviewer: review.reviewer,
+        content: review.content,
+        star: review.star,
+        verified: false,
+        edited_at: None,
+        reply: None,
+        created_at: U64(0),
+        helpful: 0,
+        unhelpful: 0,
+        hidden: None,
+        media: vec![],
+      },
+      VersionedReview::V2(review) => review,
+    }
+  }
+}
+
+impl From<Review> for VersionedReview {
+  fn from(review: Review) -> Self {
+    VersionedReview::V2(review)
+  }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct PurchaseInfoV1 {
+  pub product_id: String,
+  pub origin_price: u128,
+  pub profit_price: u128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedPurchaseInfo {
+  V1(PurchaseInfoV1),
+  V2(PurchaseInfo),
+}
+
+impl From<VersionedPurchaseInfo> for PurchaseInfo {
+  fn from(purchase: VersionedPurchaseInfo) -> Self {
+    match purchase {
+      // purchases of the first release have no receipt
+      VersionedPurchaseInfo::V1(purchase) => PurchaseInfo {
+        product_id: purchase.product_id,
+        origin_price: purchase.origin_price,
+        profit_price: purchase.profit_price,
+        receipt_id: None,
+      },
+      VersionedPurchaseInfo::V2(purchase) => purchase,
+    }
+  }
+}
+
+impl From<PurchaseInfo> for VersionedPurchaseInfo {
+  fn from(purchase: PurchaseInfo) -> Self {
+    VersionedPurchaseInfo::V2(purchase)
+  }
+}